@@ -0,0 +1,161 @@
+#[path = "../tests/common/mod.rs"]
+mod common;
+mod programs;
+
+use common::runner;
+use rip8::exec::executor::execute_instruction;
+use rip8::exec::headless::{ Backend, HeadlessRunner, DEFAULT_INSTRUCTIONS_PER_FRAME };
+use rip8::exec::quirks::Quirks;
+use rip8::io::keys::HeadlessKeyboard;
+use rip8::io::screen::Screen;
+use rip8::logic::decoder::decode_instruction;
+use rip8::mem::register::{ RegisterFile, VRegister };
+use rip8::mem::RAM_SIZE;
+
+use criterion::{ black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput };
+
+use std::env;
+use std::error::Error;
+use std::fs::{ read, read_dir };
+
+const DEFAULT_FRAMES: u64 = 600;
+const SPRITE_ADDRESS: u16 = 0x300;
+
+// Instructions timed on their own, DRW draws over the same spot so it alternates between setting and erasing
+const EXECUTED_INSTRUCTIONS: [(&str, u16); 13] = [
+    ("ld_byte", 0x6A42),
+    ("add_register", 0x8AB4),
+    ("shr", 0x8A06),
+    ("se_byte", 0x3A42),
+    ("jp", 0x1300),
+    ("ld_i", 0xA300),
+    ("add_i", 0xFA1E),
+    ("rnd", 0xCA0F),
+    ("ld_bcd", 0xFA33),
+    ("ld_array", 0xFF55),
+    ("rd_array", 0xFF65),
+    ("drw", 0xDAB5),
+    ("cls", 0x00E0),
+];
+
+// Sprites drawn aligned to a display byte, straddling two bytes, and wrapping around the screen edges
+const DRAWN_SPRITES: [(&str, u8, u8, usize); 4] = [
+    ("aligned_8x5", 8, 4, 5),
+    ("unaligned_8x5", 13, 7, 5),
+    ("unaligned_8x15", 21, 9, 15),
+    ("wrapping_8x15", 60, 28, 15),
+];
+
+fn decode(c: &mut Criterion) {
+    let opcodes: Vec<(u8, u8)> = (0..=0xFFFF_u16)
+        .map(|opcode| ((opcode >> 8) as u8, opcode as u8))
+        .filter(|(msb, lsb)| decode_instruction(*msb, *lsb).is_ok())
+        .collect();
+
+    let mut group = c.benchmark_group("decode_instruction");
+    group.throughput(Throughput::Elements(opcodes.len() as u64));
+    group.bench_function("valid_opcodes", |b| b.iter(|| {
+        for &(msb, lsb) in opcodes.iter() {
+            black_box(decode_instruction(msb, lsb).ok());
+        }
+    }));
+    group.finish();
+}
+
+fn execute(c: &mut Criterion) {
+    let mut registers = RegisterFile::new();
+    registers.PC = 0x200;
+    registers.I = SPRITE_ADDRESS;
+    registers.set_v_register(VRegister::VA, 3);
+    registers.set_v_register(VRegister::VB, 7);
+
+    let mut group = c.benchmark_group("execute_instruction");
+    for &(name, opcode) in EXECUTED_INSTRUCTIONS.iter() {
+        let instruction = decode_instruction((opcode >> 8) as u8, opcode as u8).unwrap();
+        let mut ram = [0; RAM_SIZE];
+        ram[SPRITE_ADDRESS as usize..SPRITE_ADDRESS as usize + 0x10].copy_from_slice(&[0xFF; 0x10]);
+        let mut screen = Screen::new();
+        let keyboard = HeadlessKeyboard::new();
+        let mut key_wait = None;
+
+        group.bench_function(name, |b| b.iter(|| {
+            let mut registers = registers;
+            execute_instruction(instruction, &mut registers, &mut ram, &keyboard, &mut screen,
+                                Quirks::default(), &mut key_wait).unwrap()
+        }));
+    }
+    group.finish();
+}
+
+fn draw(c: &mut Criterion) {
+    let mut group = c.benchmark_group("screen_draw");
+    for &(name, x, y, rows) in DRAWN_SPRITES.iter() {
+        let sprite = vec![0xA5; rows];
+        let mut screen = Screen::new();
+
+        group.bench_function(name, |b| b.iter(|| screen.draw(x, y, sprite.clone(), 0x200, SPRITE_ADDRESS).unwrap()));
+    }
+    group.finish();
+}
+
+fn run_frames(mut runner: HeadlessRunner, frames: u64) -> Result<(), Box<dyn Error>> {
+    while runner.frames() < frames {
+        runner.run_frame()?;
+    }
+
+    Ok(())
+}
+
+// The built-in programs, plus every .ch8 file in the directory named by RIP8_BENCH_ROMS
+fn roms() -> Vec<(String, Vec<u8>)> {
+    let mut roms: Vec<(String, Vec<u8>)> = programs::built_in()
+        .into_iter()
+        .map(|(name, program)| (name.to_string(), program))
+        .collect();
+
+    let directory = match env::var("RIP8_BENCH_ROMS") {
+        Ok(directory) => directory,
+        Err(_) => return roms,
+    };
+    let mut paths: Vec<_> = read_dir(&directory)
+        .unwrap_or_else(|error| panic!("Error while reading {}: {}", directory, error))
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension() == Some("ch8".as_ref()))
+        .collect();
+    paths.sort();
+
+    for path in paths {
+        let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+        roms.push((name, read(&path).unwrap()));
+    }
+
+    roms
+}
+
+fn headless(c: &mut Criterion) {
+    let frames = env::var("RIP8_BENCH_FRAMES").ok().and_then(|frames| frames.parse().ok()).unwrap_or(DEFAULT_FRAMES);
+
+    let mut group = c.benchmark_group(format!("headless_{}_frames", frames));
+    group.sample_size(20);
+    for (name, program) in roms() {
+        // ROMs using instructions rip8 does not support are left out instead of failing the whole run
+        if let Err(error) = run_frames(runner(&program, DEFAULT_INSTRUCTIONS_PER_FRAME, Quirks::default(), Backend::Interpreter), frames) {
+            println!("Skipping {}: {}", name, error);
+            continue;
+        }
+
+        for &backend in [Backend::Interpreter, Backend::BlockCompiler].iter() {
+            let id = BenchmarkId::new(&name, format!("{:?}", backend));
+            // Setting up the runner is left out, so only the emulation itself is timed
+            group.bench_with_input(id, &program, |b, program| b.iter_batched(
+                || runner(program, DEFAULT_INSTRUCTIONS_PER_FRAME, Quirks::default(), backend),
+                |runner| run_frames(runner, frames).unwrap(),
+                BatchSize::LargeInput,
+            ));
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, decode, execute, draw, headless);
+criterion_main!(benches);
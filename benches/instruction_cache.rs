@@ -0,0 +1,38 @@
+#[path = "../tests/common/mod.rs"]
+mod common;
+// Only the draw loop is used here
+#[allow(dead_code)]
+mod programs;
+
+use common::{ assemble, runner };
+use rip8::exec::headless::Backend;
+use rip8::exec::quirks::Quirks;
+
+use criterion::{ criterion_group, criterion_main, BenchmarkId, Criterion };
+
+const FRAMES: u64 = 600;
+const INSTRUCTIONS_PER_FRAME: u32 = 1000;
+
+fn run_frames(instruction_cache: bool) {
+    let mut runner = runner(&assemble(&programs::DRAW_LOOP), INSTRUCTIONS_PER_FRAME, Quirks::default(), Backend::Interpreter);
+    runner.set_instruction_cache(instruction_cache);
+
+    while runner.frames() < FRAMES {
+        runner.run_frame().unwrap();
+    }
+}
+
+fn instruction_cache(c: &mut Criterion) {
+    let mut group = c.benchmark_group("headless_frames");
+    group.sample_size(20);
+
+    for &enabled in [false, true].iter() {
+        let name = if enabled { "cached" } else { "decoded" };
+        group.bench_with_input(BenchmarkId::new(name, FRAMES), &enabled, |b, &enabled| b.iter(|| run_frames(enabled)));
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, instruction_cache);
+criterion_main!(benches);
@@ -0,0 +1,105 @@
+// Small programs standing in for games, each looping forever over the kind of work common ROMs do every frame
+
+use crate::common::assemble;
+
+// Arithmetic and branches looping over a draw, the mix most games spend their time in
+pub const DRAW_LOOP: [u16; 11] = [
+    0x6000, // 200: LD V0, 00
+    0x6101, // 202: LD V1, 01
+    0xF129, // 204: LD F, V1
+    0xD015, // 206: DRW V0, V1, 5
+    0x7001, // 208: ADD V0, 01
+    0x8214, // 20A: ADD V2, V1
+    0x8206, // 20C: SHR V2
+    0x3040, // 20E: SE V0, 40
+    0x1208, // 210: JP 208
+    0x6000, // 212: LD V0, 00
+    0x1206, // 214: JP 206
+];
+
+// A sprite moved one pixel a frame, erased and redrawn and bounced off the screen edges
+pub const BOUNCING_BALL: [u16; 24] = [
+    0x6020, // 200: LD V0, 20
+    0x6110, // 202: LD V1, 10
+    0x6201, // 204: LD V2, 01
+    0x6301, // 206: LD V3, 01
+    0xA22E, // 208: LD I, 22E
+    0xD011, // 20A: DRW V0, V1, 1
+    0xD011, // 20C: DRW V0, V1, 1
+    0x8024, // 20E: ADD V0, V2
+    0x8134, // 210: ADD V1, V3
+    0x303F, // 212: SE V0, 3F
+    0x1218, // 214: JP 218
+    0x62FF, // 216: LD V2, FF
+    0x3000, // 218: SE V0, 00
+    0x121E, // 21A: JP 21E
+    0x6201, // 21C: LD V2, 01
+    0x311F, // 21E: SE V1, 1F
+    0x1224, // 220: JP 224
+    0x63FF, // 222: LD V3, FF
+    0x3100, // 224: SE V1, 00
+    0x122A, // 226: JP 22A
+    0x6301, // 228: LD V3, 01
+    0xD011, // 22A: DRW V0, V1, 1
+    0x120C, // 22C: JP 20C
+    0x8000, // 22E: sprite
+];
+
+// A counter redrawn in decimal after every short DT delay, like a score display
+pub const SCORE_COUNTER: [u16; 22] = [
+    0x6A00, // 200: LD VA, 00
+    0x00E0, // 202: CLS
+    0xA300, // 204: LD I, 300
+    0xFA33, // 206: LD B, VA
+    0xF265, // 208: LD V2, [I]
+    0x6B00, // 20A: LD VB, 00
+    0x6C00, // 20C: LD VC, 00
+    0xF029, // 20E: LD F, V0
+    0xDBC5, // 210: DRW VB, VC, 5
+    0x7B05, // 212: ADD VB, 05
+    0xF129, // 214: LD F, V1
+    0xDBC5, // 216: DRW VB, VC, 5
+    0x7B05, // 218: ADD VB, 05
+    0xF229, // 21A: LD F, V2
+    0xDBC5, // 21C: DRW VB, VC, 5
+    0x7A01, // 21E: ADD VA, 01
+    0x6D02, // 220: LD VD, 02
+    0xFD15, // 222: LD DT, VD
+    0xFE07, // 224: LD VE, DT
+    0x3E00, // 226: SE VE, 00
+    0x1224, // 228: JP 224
+    0x1202, // 22A: JP 202
+];
+
+// Long runs of register arithmetic without draws, like the setup and physics code between frames
+pub const ARITHMETIC: [u16; 20] = [
+    0x6001, // 200: LD V0, 01
+    0x6105, // 202: LD V1, 05
+    0x8014, // 204: ADD V0, V1
+    0x8206, // 206: SHR V2
+    0x8103, // 208: XOR V1, V0
+    0x720D, // 20A: ADD V2, 0D
+    0x8325, // 20C: SUB V3, V2
+    0x8431, // 20E: OR V4, V3
+    0x850E, // 210: SHL V5
+    0x8542, // 212: AND V5, V4
+    0x8657, // 214: SUBN V6, V5
+    0x8760, // 216: LD V7, V6
+    0x7711, // 218: ADD V7, 11
+    0x8874, // 21A: ADD V8, V7
+    0x8986, // 21C: SHR V9
+    0x8983, // 21E: XOR V9, V8
+    0xF91E, // 220: ADD I, V9
+    0xA300, // 222: LD I, 300
+    0x8094, // 224: ADD V0, V9
+    0x1204, // 226: JP 204
+];
+
+pub fn built_in() -> Vec<(&'static str, Vec<u8>)> {
+    vec![
+        ("draw_loop", assemble(&DRAW_LOOP)),
+        ("bouncing_ball", assemble(&BOUNCING_BALL)),
+        ("score_counter", assemble(&SCORE_COUNTER)),
+        ("arithmetic", assemble(&ARITHMETIC)),
+    ]
+}
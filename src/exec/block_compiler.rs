@@ -0,0 +1,180 @@
+use super::cpu::{ RAM_DIGIT_SPRITE_START, DIGIT_SPRITE_SIZE };
+use super::quirks::MemoryBounds;
+use crate::logic::decoder;
+use crate::logic::instruction::{ ByteOrVReg, Instruction };
+use crate::mem::register::{ RegisterFile, VRegister, V_REGISTERS };
+use crate::mem::{ RAM, RAM_SIZE };
+
+use std::ops::Range;
+
+use rand::prelude::*;
+use rand::thread_rng;
+
+// Longer runs are split, which also bounds how far back a RAM write has to look for blocks covering it
+pub const MAX_BLOCK_INSTRUCTIONS: usize = 64;
+const INSTRUCTION_SIZE: usize = 2;
+
+type Operation = Box<dyn Fn(&mut RegisterFile, &RAM) + Send + Sync>;
+
+// Straight-line instructions compiled into closures with their operands already decoded
+pub struct Block {
+    instructions: Vec<Instruction>,
+    operations: Vec<Operation>,
+}
+
+impl Block {
+    pub fn len(&self) -> usize {
+        self.operations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+
+    // Runs up to max_instructions of the block and returns the last one run
+    pub fn run(&self, registers: &mut RegisterFile, ram: &RAM, max_instructions: usize) -> Option<Instruction> {
+        let count = self.len().min(max_instructions);
+        for operation in &self.operations[..count] {
+            operation(registers, ram);
+        }
+        registers.PC += (count * INSTRUCTION_SIZE) as u16;
+
+        count.checked_sub(1).map(|last| self.instructions[last])
+    }
+}
+
+fn byte_or_register(registers: &RegisterFile, byte_or_reg: ByteOrVReg) -> u8 {
+    match byte_or_reg {
+        ByteOrVReg::Byte(val) => val,
+        ByteOrVReg::Register(reg) => registers.get_v_register(reg),
+    }
+}
+
+// Jumps, skips, draws, key waits and RAM writes end a block and are left to the interpreter
+fn compile(instruction: Instruction, memory_bounds: MemoryBounds) -> Option<Operation> {
+    let operation: Operation = match instruction {
+        Instruction::LD(reg, ByteOrVReg::Byte(val)) => Box::new(move |registers, _| registers.set_v_register(reg, val)),
+        Instruction::LD(reg, ByteOrVReg::Register(src)) => Box::new(move |registers, _| {
+            registers.set_v_register(reg, registers.get_v_register(src));
+        }),
+        Instruction::ADD(reg, byte_or_reg) => Box::new(move |registers, _| {
+            let (result, carry) = registers.get_v_register(reg).overflowing_add(byte_or_register(registers, byte_or_reg));
+            registers.set_v_register(VRegister::VF, carry as u8);
+            registers.set_v_register(reg, result);
+        }),
+        Instruction::SUB(reg_a, reg_b) => Box::new(move |registers, _| {
+            let (result, borrow) = registers.get_v_register(reg_a).overflowing_sub(registers.get_v_register(reg_b));
+            registers.set_v_register(VRegister::VF, (!borrow) as u8);
+            registers.set_v_register(reg_a, result);
+        }),
+        Instruction::SUBN(reg_a, reg_b) => Box::new(move |registers, _| {
+            let (result, borrow) = registers.get_v_register(reg_b).overflowing_sub(registers.get_v_register(reg_a));
+            registers.set_v_register(VRegister::VF, (!borrow) as u8);
+            registers.set_v_register(reg_a, result);
+        }),
+        Instruction::OR(reg_a, reg_b) => Box::new(move |registers, _| {
+            registers.set_v_register(reg_a, registers.get_v_register(reg_a) | registers.get_v_register(reg_b));
+        }),
+        Instruction::AND(reg_a, reg_b) => Box::new(move |registers, _| {
+            registers.set_v_register(reg_a, registers.get_v_register(reg_a) & registers.get_v_register(reg_b));
+        }),
+        Instruction::XOR(reg_a, reg_b) => Box::new(move |registers, _| {
+            registers.set_v_register(reg_a, registers.get_v_register(reg_a) ^ registers.get_v_register(reg_b));
+        }),
+        Instruction::SHL(reg) => Box::new(move |registers, _| {
+            let (result, flag) = registers.get_v_register(reg).overflowing_shl(1);
+            registers.set_v_register(VRegister::VF, flag as u8);
+            registers.set_v_register(reg, result);
+        }),
+        Instruction::SHR(reg) => Box::new(move |registers, _| {
+            let (result, flag) = registers.get_v_register(reg).overflowing_shr(1);
+            registers.set_v_register(VRegister::VF, flag as u8);
+            registers.set_v_register(reg, result);
+        }),
+        Instruction::LDI(addr) => Box::new(move |registers, _| registers.I = addr & 0x0FFF),
+        Instruction::ADDI(reg) => Box::new(move |registers, _| {
+            registers.I = (registers.get_v_register(reg) as u16).wrapping_add(registers.I);
+        }),
+        Instruction::LDF(reg) => Box::new(move |registers, _| {
+            let digit = registers.get_v_register(reg) as usize;
+            registers.I = (RAM_DIGIT_SPRITE_START + DIGIT_SPRITE_SIZE * digit) as u16;
+        }),
+        Instruction::RND(reg, byte) => Box::new(move |registers, _| {
+            registers.set_v_register(reg, thread_rng().next_u32() as u8 & byte);
+        }),
+        Instruction::LD_FROM_DT(reg) => Box::new(move |registers, _| registers.set_v_register(reg, registers.DT)),
+        Instruction::LD_TO_DT(reg) => Box::new(move |registers, _| registers.DT = registers.get_v_register(reg)),
+        Instruction::LDST(reg) => Box::new(move |registers, _| registers.ST = registers.get_v_register(reg)),
+        // Reads that can fail are left to the interpreter, which reports them
+        Instruction::RDARR(_) if memory_bounds == MemoryBounds::Error => return None,
+        Instruction::RDARR(end_reg) => Box::new(move |registers, ram| {
+            for (index, reg) in V_REGISTERS[..=end_reg as usize].iter().enumerate() {
+                let val = memory_bounds.read(ram, registers.I as usize + index).unwrap_or(0);
+                registers.set_v_register(*reg, val);
+            }
+        }),
+        _ => return None,
+    };
+
+    Some(operation)
+}
+
+fn compile_block(ram: &RAM, start: usize, memory_bounds: MemoryBounds) -> Block {
+    let mut instructions = vec![];
+    let mut operations = vec![];
+
+    let mut address = start;
+    while operations.len() < MAX_BLOCK_INSTRUCTIONS && address + 1 < RAM_SIZE {
+        let instruction = match decoder::decode_instruction(ram[address], ram[address + 1]) {
+            Ok(instruction) => instruction,
+            Err(_) => break,
+        };
+        let operation = match compile(instruction, memory_bounds) {
+            Some(operation) => operation,
+            None => break,
+        };
+
+        instructions.push(instruction);
+        operations.push(operation);
+        address += INSTRUCTION_SIZE;
+    }
+
+    Block { instructions, operations }
+}
+
+// Compiled blocks by start address, dropped when RAM under them is written
+pub struct BlockCache {
+    blocks: Vec<Option<Block>>,
+    memory_bounds: MemoryBounds,
+}
+
+impl BlockCache {
+    pub fn new(memory_bounds: MemoryBounds) -> BlockCache {
+        BlockCache {
+            blocks: (0..RAM_SIZE).map(|_| None).collect(),
+            memory_bounds,
+        }
+    }
+
+    // Addresses past the end of RAM have no block, the interpreter reports them
+    pub fn block(&mut self, address: usize, ram: &RAM) -> Option<&Block> {
+        let memory_bounds = self.memory_bounds;
+        let block = self.blocks.get_mut(address)?;
+        Some(block.get_or_insert_with(|| compile_block(ram, address, memory_bounds)))
+    }
+
+    pub fn invalidate(&mut self, written: Range<usize>) {
+        let first = written.start.saturating_sub(MAX_BLOCK_INSTRUCTIONS * INSTRUCTION_SIZE).min(RAM_SIZE);
+        let end = written.end.min(RAM_SIZE).max(first);
+
+        for start in first..end {
+            let covers_write = match &self.blocks[start] {
+                Some(block) => start + block.len() * INSTRUCTION_SIZE > written.start,
+                None => false,
+            };
+            if covers_write {
+                self.blocks[start] = None;
+            }
+        }
+    }
+}
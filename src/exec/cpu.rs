@@ -1,5 +1,11 @@
-use super::executor;
-use crate::io::keys::Keyboard;
+use super::block_compiler::BlockCache;
+use super::error::{ EmulatorError, MachineState };
+use super::executor::{ self, Stall };
+use super::instruction_cache::{ self, InstructionCache };
+use super::quirks::{ MemoryBounds, Quirks };
+use super::trace::{InstructionTrace, TraceEntry};
+use super::vip_timing;
+use crate::io::keys::{Key, KeyStates, KeyTransitions, Keyboard};
 use crate::io::screen::Screen;
 use crate::logic::decoder;
 use crate::logic::instruction::Instruction;
@@ -7,6 +13,7 @@ use crate::mem::register::RegisterFile;
 use crate::mem::{RAM, RAM_SIZE};
 
 use std::error::Error;
+use std::ops::Range;
 
 pub const RAM_PROG_START: usize = 0x200;
 pub const RAM_DIGIT_SPRITE_START: usize = 0xFF;
@@ -37,16 +44,30 @@ pub struct CPU {
     register_file: RegisterFile,
     ram: RAM,
     last_instruction: Option<Instruction>,
-    instruction_trace: Option<Vec<(u16, Instruction)>>,
+    instruction_trace: Option<InstructionTrace>,
+    cycles: u64,
+    last_machine_cycles: u32,
+    quirks: Quirks,
+    key_states: KeyStates,
+    key_wait: Option<Key>,
+    instruction_cache: Option<InstructionCache>,
+    block_cache: Option<BlockCache>,
 }
 
 impl CPU {
-    pub fn new(enable_instruction_tracing: bool) -> CPU {
+    pub fn new(instruction_trace_capacity: Option<usize>) -> CPU {
         let mut cpu = CPU {
             register_file: RegisterFile::new(),
             ram: [0; RAM_SIZE],
             last_instruction: None,
-            instruction_trace: Some(vec![]),
+            instruction_trace: instruction_trace_capacity.map(InstructionTrace::new),
+            cycles: 0,
+            last_machine_cycles: 0,
+            quirks: Quirks::default(),
+            key_states: [false; 16],
+            key_wait: None,
+            instruction_cache: Some(InstructionCache::new()),
+            block_cache: None,
         };
 
         cpu.register_file.PC = RAM_PROG_START as u16;
@@ -54,6 +75,16 @@ impl CPU {
         cpu
     }
 
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+        // Blocks are compiled for the memory bounds they were created under
+        self.block_cache = None;
+    }
+
+    pub fn set_instruction_cache(&mut self, enabled: bool) {
+        self.instruction_cache = if enabled { Some(InstructionCache::new()) } else { None };
+    }
+
     fn map_data(&mut self, data: Vec<u8>, start: usize) -> Result<(), RAMOutOfBoundsError> {
         let data_length = data.len();
         if data_length > self.ram.len() - start {
@@ -62,6 +93,7 @@ impl CPU {
 
         let program_area = &mut self.ram[start..start + data_length];
         program_area.copy_from_slice(data.as_slice());
+        self.invalidate_caches(start..start + data_length);
 
         Ok(())
     }
@@ -96,34 +128,43 @@ impl CPU {
 
     fn get_next_instruction_bytes(&self) -> Result<(u8, u8), RAMOutOfBoundsError> {
         let msb_address = self.register_file.PC as usize;
-        let lsb_address = (self.register_file.PC + 1) as usize;
-        if lsb_address > RAM_SIZE {
-            return Err(RAMOutOfBoundsError::new());
-        }
-        Ok((self.ram[msb_address], self.ram[lsb_address]))
+        let lsb_address = msb_address + 1;
+        let bounds = self.quirks.memory_bounds;
+        Ok((bounds.read(&self.ram, msb_address)?, bounds.read(&self.ram, lsb_address)?))
     }
 
-    pub fn execute_cycle(&mut self, keyboard: impl Keyboard, screen: &mut Screen) -> Result<(), Box<dyn Error>> {
-        let (msb, lsb) = self.get_next_instruction_bytes()?;
+    pub fn execute_cycle(&mut self, keyboard: impl Keyboard, screen: &mut Screen) -> Result<Stall, EmulatorError> {
+        let registers_before = self.register_file;
+        let (msb, lsb) = self.get_next_instruction_bytes()
+            .map_err(|error| EmulatorError::RAMOutOfBounds(error, MachineState::new(registers_before, None)))?;
+        let opcode = ((msb as u16) << 8) + lsb as u16;
+
+        // Instructions reaching past the end of RAM are left out of the cache, they are not kept up to date with writes
+        let pc = self.register_file.PC as usize;
+        let instruction = match &mut self.instruction_cache {
+            Some(cache) if pc + 1 < RAM_SIZE => cache.decode(pc, msb, lsb),
+            _ => decoder::decode_instruction(msb, lsb),
+        }.map_err(|error| EmulatorError::MalformedInstruction(error, MachineState::new(registers_before, Some(opcode))))?;
 
-        let instruction = decoder::decode_instruction(msb, lsb)?;
+        let keyboard = KeyTransitions::new(&keyboard, self.key_states);
+        self.key_states = keyboard.key_states();
 
-        executor::execute_instruction(
+        let outcome = executor::execute_instruction(
             instruction,
             &mut self.register_file,
             &mut self.ram,
             &keyboard,
             screen,
-        )?;
+            self.quirks,
+            &mut self.key_wait,
+        ).map_err(|error| EmulatorError::from_execution(error, MachineState::new(registers_before, Some(opcode))))?;
 
         self.last_instruction = Some(instruction);
 
-        match &mut self.instruction_trace {
-            Some(trace) => {
-                trace.push((self.register_file.PC, instruction));
-            },
-            None => {},
-        };
+        // Programs that write over their own code get the new instructions decoded
+        if let Some(written) = instruction_cache::ram_writes(instruction, &registers_before) {
+            self.invalidate_caches(written);
+        }
 
         match instruction {
             Instruction::JP(_) | Instruction::JPV0(_) | Instruction::CALL(_) => {}
@@ -131,14 +172,99 @@ impl CPU {
                 self.register_file.PC += 2;
             }
         };
+        self.wrap_pc();
 
-        Ok(())
+        match &mut self.instruction_trace {
+            Some(trace) => {
+                trace.record(TraceEntry {
+                    cycle: self.cycles,
+                    pc: registers_before.PC,
+                    opcode,
+                    instruction,
+                    registers: registers_before,
+                    deltas: registers_before.deltas(&self.register_file),
+                });
+            },
+            None => {},
+        };
+
+        self.last_machine_cycles = vip_timing::machine_cycles(instruction, &registers_before, outcome.skipped);
+
+        self.cycles += 1;
+
+        Ok(outcome.stall)
+    }
+
+    // Runs the compiled straight-line instructions at PC, then the instruction ending the block through the
+    // interpreter, returning how many instructions ran
+    pub fn execute_block(&mut self, keyboard: impl Keyboard, screen: &mut Screen,
+                         max_instructions: u32) -> Result<(u32, Stall), EmulatorError> {
+        if max_instructions == 0 {
+            return Ok((0, Stall::None));
+        }
+
+        // Traces are recorded one instruction at a time
+        if self.instruction_trace.is_some() {
+            let stall = self.execute_cycle(keyboard, screen)?;
+            return Ok((1, stall));
+        }
+
+        let pc = self.register_file.PC as usize;
+        let mut executed = 0;
+
+        let memory_bounds = self.quirks.memory_bounds;
+        if let Some(block) = self.block_cache.get_or_insert_with(|| BlockCache::new(memory_bounds)).block(pc, &self.ram) {
+            executed = block.len().min(max_instructions as usize);
+
+            if let Some(last_instruction) = block.run(&mut self.register_file, &self.ram, executed) {
+                self.wrap_pc();
+                self.last_instruction = Some(last_instruction);
+                self.last_machine_cycles = vip_timing::machine_cycles(last_instruction, &self.register_file, false);
+                self.cycles += executed as u64;
+            }
+        }
+
+        if executed as u32 == max_instructions {
+            return Ok((executed as u32, Stall::None));
+        }
+
+        let stall = self.execute_cycle(keyboard, screen)?;
+        Ok((executed as u32 + 1, stall))
+    }
+
+    // Wrapped addresses include PC, so it never leaves the 12-bit address space
+    fn wrap_pc(&mut self) {
+        if self.quirks.memory_bounds == MemoryBounds::Wrap {
+            self.register_file.PC &= (RAM_SIZE - 1) as u16;
+        }
+    }
+
+    fn invalidate_caches(&mut self, written: Range<usize>) {
+        // Writes past the end of RAM either went nowhere or wrapped around to its start
+        if written.end > RAM_SIZE {
+            for address in written {
+                let wrapped = address % RAM_SIZE;
+                self.invalidate_caches(wrapped..wrapped + 1);
+            }
+            return;
+        }
+
+        if let Some(cache) = &mut self.instruction_cache {
+            cache.invalidate(written.clone());
+        }
+        if let Some(cache) = &mut self.block_cache {
+            cache.invalidate(written);
+        }
     }
 
     pub fn inspect_last_instruction(&self) -> Option<Instruction> {
         self.last_instruction
     }
 
+    pub fn inspect_last_machine_cycles(&self) -> u32 {
+        self.last_machine_cycles
+    }
+
     pub fn inspect_register_file(&self) -> RegisterFile {
         self.register_file
     }
@@ -152,8 +278,17 @@ impl CPU {
         &self.ram
     }
 
-    pub fn get_instruction_trace(&self) -> Option<Vec<(u16, Instruction)>> {
-        self.instruction_trace.clone()
+    pub fn inspect_stack(&self) -> Vec<u16> {
+        let sp = (self.register_file.SP as usize).min(executor::STACK_SIZE);
+        self.ram[..sp]
+            .chunks(executor::STACK_FRAME_SIZE)
+            .filter(|frame| frame.len() == executor::STACK_FRAME_SIZE)
+            .map(|frame| ((frame[0] as u16) << 8) + frame[1] as u16)
+            .collect()
+    }
+
+    pub fn inspect_instruction_trace(&self) -> Option<&InstructionTrace> {
+        self.instruction_trace.as_ref()
     }
 
     pub fn tick_timers(&mut self) {
@@ -166,3 +301,36 @@ impl CPU {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::CPU;
+    use crate::exec::quirks::Quirks;
+    use crate::io::keys::HeadlessKeyboard;
+    use crate::io::screen::Screen;
+
+    // Runs the opcode at address with wrapped memory, returning PC and the machine cycles it took
+    fn run_wrapped(address: usize, opcode: [u8; 2]) -> (u16, u32) {
+        let mut cpu = CPU::new(None);
+        cpu.set_quirks("memory-wrap".parse::<Quirks>().unwrap());
+        cpu.ram[address..address + 2].copy_from_slice(&opcode);
+        cpu.register_file.PC = address as u16;
+
+        cpu.execute_cycle(HeadlessKeyboard::new(), &mut Screen::new()).unwrap();
+        (cpu.inspect_register_file().PC, cpu.inspect_last_machine_cycles())
+    }
+
+    #[test]
+    fn skips_wrapping_past_the_end_of_ram_take_skip_cycles() {
+        // SE V0, 00 skips, since V0 starts at zero
+        assert_eq!(run_wrapped(0xFFE, [0x30, 0x00]), (0x002, 40 + 10 + 4));
+        // SNE V0, 00 does not
+        assert_eq!(run_wrapped(0xFFE, [0x40, 0x00]), (0x000, 40 + 10));
+    }
+
+    #[test]
+    fn jumps_four_bytes_ahead_are_not_skips() {
+        assert_eq!(run_wrapped(0x200, [0x12, 0x04]), (0x204, 40 + 12));
+        assert_eq!(run_wrapped(0xFFC, [0x10, 0x00]), (0x000, 40 + 12));
+    }
+}
@@ -0,0 +1,108 @@
+use super::cpu::{ RAMOutOfBoundsError, CPU };
+use super::executor::{ ExecutionError, InvalidStackPointerError };
+use crate::io::screen::InvalidSpriteSizeError;
+use crate::logic::decoder::MalformedInstructionError;
+use crate::mem::register::RegisterFile;
+
+use serde::Serialize;
+
+use std::error::Error;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{ Path, PathBuf };
+use std::time::{ SystemTime, UNIX_EPOCH };
+
+// The registers before the failing instruction ran, PC pointing at it
+#[derive(Debug, Copy, Clone, Serialize)]
+pub struct MachineState {
+    pub pc: u16,
+    // Not known when the instruction could not be fetched
+    pub opcode: Option<u16>,
+    pub registers: RegisterFile,
+}
+
+impl MachineState {
+    pub fn new(registers: RegisterFile, opcode: Option<u16>) -> MachineState {
+        MachineState {
+            pc: registers.PC,
+            opcode,
+            registers,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum EmulatorError {
+    RAMOutOfBounds(RAMOutOfBoundsError, MachineState),
+    InvalidStackPointer(InvalidStackPointerError, MachineState),
+    MalformedInstruction(MalformedInstructionError, MachineState),
+    InvalidSpriteSize(InvalidSpriteSizeError, MachineState),
+}
+
+impl EmulatorError {
+    pub fn from_execution(error: ExecutionError, state: MachineState) -> EmulatorError {
+        match error {
+            ExecutionError::RAMOutOfBounds(error) => EmulatorError::RAMOutOfBounds(error, state),
+            ExecutionError::InvalidStackPointer(error) => EmulatorError::InvalidStackPointer(error, state),
+            ExecutionError::InvalidSpriteSize(error) => EmulatorError::InvalidSpriteSize(error, state),
+        }
+    }
+
+    pub fn state(&self) -> &MachineState {
+        match self {
+            EmulatorError::RAMOutOfBounds(_, state) |
+            EmulatorError::InvalidStackPointer(_, state) |
+            EmulatorError::MalformedInstruction(_, state) |
+            EmulatorError::InvalidSpriteSize(_, state) => state,
+        }
+    }
+
+    fn cause(&self) -> &(dyn Error + 'static) {
+        match self {
+            EmulatorError::RAMOutOfBounds(error, _) => error,
+            EmulatorError::InvalidStackPointer(error, _) => error,
+            EmulatorError::MalformedInstruction(error, _) => error,
+            EmulatorError::InvalidSpriteSize(error, _) => error,
+        }
+    }
+}
+
+impl std::fmt::Display for EmulatorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = self.state();
+        match state.opcode {
+            Some(opcode) => write!(f, "{} at {:03X} (opcode {:04X})", self.cause(), state.pc, opcode),
+            None => write!(f, "{} at {:03X}", self.cause(), state.pc),
+        }
+    }
+}
+
+impl Error for EmulatorError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.cause())
+    }
+}
+
+// Everything needed to look into a crash after rip8 has exited
+#[derive(Serialize)]
+struct CrashDump<'a> {
+    error: String,
+    state: &'a MachineState,
+    stack: Vec<u16>,
+    memory: &'a [u8],
+}
+
+pub fn save_crash_dump(cpu: &CPU, error: &EmulatorError, directory: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+    let path = directory.join(format!("rip8-crash-{}.json", timestamp));
+
+    let dump = CrashDump {
+        error: error.to_string(),
+        state: error.state(),
+        stack: cpu.inspect_stack(),
+        memory: &cpu.inspect_memory()[..],
+    };
+    serde_json::to_writer_pretty(BufWriter::new(File::create(&path)?), &dump)?;
+
+    Ok(path)
+}
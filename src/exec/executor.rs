@@ -1,8 +1,9 @@
 use crate::io::keys::{ Key, Keyboard };
-use crate::io::screen::Screen;
+use crate::io::screen::{ InvalidSpriteSizeError, Screen };
 use crate::logic::instruction::{ByteOrVReg, Instruction};
 use crate::mem::register::{RegisterFile, VRegister};
 use super::cpu::{ RAMOutOfBoundsError, RAM_DIGIT_SPRITE_START, DIGIT_SPRITE_SIZE };
+use super::quirks::Quirks;
 use crate::mem::RAM;
 
 use std::error::Error;
@@ -10,6 +11,81 @@ use std::error::Error;
 use rand::prelude::*;
 use rand::thread_rng;
 
+// Tells the scheduler an instruction has to wait before the CPU carries on
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Stall {
+    None,
+    // Nothing else runs until the next frame boundary
+    DisplayWait,
+    // Spinning in a loop that only ends once DT changes, so the rest of the frame can be skipped
+    Idle,
+    // Jumped to itself, the program can never run anything else
+    Halted,
+}
+
+// How an instruction ended, skips are reported since a wrapped PC cannot tell them apart from short jumps
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Outcome {
+    pub stall: Stall,
+    pub skipped: bool,
+}
+
+impl Outcome {
+    fn skipped() -> Outcome {
+        Outcome {
+            stall: Stall::None,
+            skipped: true,
+        }
+    }
+}
+
+impl From<Stall> for Outcome {
+    fn from(stall: Stall) -> Outcome {
+        Outcome {
+            stall,
+            skipped: false,
+        }
+    }
+}
+
+// Errors an instruction can run into, the CPU adds the machine state they happened in
+#[derive(Debug)]
+pub enum ExecutionError {
+    RAMOutOfBounds(RAMOutOfBoundsError),
+    InvalidStackPointer(InvalidStackPointerError),
+    InvalidSpriteSize(InvalidSpriteSizeError),
+}
+
+impl std::fmt::Display for ExecutionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExecutionError::RAMOutOfBounds(error) => write!(f, "{}", error),
+            ExecutionError::InvalidStackPointer(error) => write!(f, "{}", error),
+            ExecutionError::InvalidSpriteSize(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl Error for ExecutionError {}
+
+impl From<RAMOutOfBoundsError> for ExecutionError {
+    fn from(error: RAMOutOfBoundsError) -> ExecutionError {
+        ExecutionError::RAMOutOfBounds(error)
+    }
+}
+
+impl From<InvalidStackPointerError> for ExecutionError {
+    fn from(error: InvalidStackPointerError) -> ExecutionError {
+        ExecutionError::InvalidStackPointer(error)
+    }
+}
+
+impl From<InvalidSpriteSizeError> for ExecutionError {
+    fn from(error: InvalidSpriteSizeError) -> ExecutionError {
+        ExecutionError::InvalidSpriteSize(error)
+    }
+}
+
 #[derive(Debug)]
 pub struct InvalidStackPointerError {
     msg: &'static str,
@@ -41,7 +117,8 @@ pub const STACK_FRAME_SIZE: usize = 2;
 pub const STACK_SIZE: usize = MAX_STACK_FRAMES * STACK_FRAME_SIZE;
 
 fn push_pc(ram: &mut RAM, sp: &mut u8, pc: u16) -> Result<(), InvalidStackPointerError> {
-    if *sp as usize > STACK_SIZE {
+    // The stack has its own region at the start of RAM, a 17th frame would write past it
+    if *sp as usize + STACK_FRAME_SIZE > STACK_SIZE {
         return Err(InvalidStackPointerError::overflow());
     }
     let pc_msb = ((pc & 0xFF00) >> 8) as u8;
@@ -86,6 +163,22 @@ fn get_pressed_key(keyboard: &dyn Keyboard) -> Option<Key> {
     None
 }
 
+fn get_released_key(keyboard: &dyn Keyboard, key_wait: &mut Option<Key>) -> Option<Key> {
+    match *key_wait {
+        Some(key) => {
+            if keyboard.was_key_released(key) {
+                *key_wait = None;
+                return Some(key);
+            }
+        },
+        None => {
+            *key_wait = (0x0..(0xF + 1)).find(|key| keyboard.was_key_pressed(*key));
+        },
+    }
+
+    None
+}
+
 fn get_v_register_range(end: VRegister) -> Vec<VRegister> {
     match end {
         VRegister::V0 => vec![VRegister::V0],
@@ -107,13 +200,26 @@ fn get_v_register_range(end: VRegister) -> Vec<VRegister> {
     }
 }
 
+// Matches LD Vx, DT / SE Vx, 0 / JP back to the LD, which leaves the loop only once DT reaches 0
+fn is_delay_loop(ram: &RAM, loop_start: u16, jump_address: u16) -> bool {
+    let start = loop_start as usize;
+    if loop_start.wrapping_add(4) != jump_address || start + 3 >= ram.len() {
+        return false;
+    }
+
+    let x = ram[start] & 0x0F;
+    ram[start] == 0xF0 | x && ram[start + 1] == 0x07 && ram[start + 2] == 0x30 | x && ram[start + 3] == 0x00
+}
+
 pub fn execute_instruction(
     instruction: Instruction,
     register_file: &mut RegisterFile,
     ram: &mut RAM,
     keyboard: &dyn Keyboard,
-    screen: &mut Screen
-) -> Result<(), Box<dyn Error>> {
+    screen: &mut Screen,
+    quirks: Quirks,
+    key_wait: &mut Option<Key>
+) -> Result<Outcome, ExecutionError> {
     match instruction {
         Instruction::CLS => {
             screen.clear();
@@ -124,22 +230,36 @@ pub fn execute_instruction(
         },
         Instruction::LDI(addr) => register_file.I = addr & 0x0FFF,
         Instruction::LDK(reg) => {
-            match get_pressed_key(&keyboard) {
+            let key = if quirks.key_wait_held {
+                get_pressed_key(&keyboard)
+            } else {
+                get_released_key(&keyboard, key_wait)
+            };
+
+            match key {
                 Some(key) => register_file.set_v_register(reg, key),
                 None => register_file.PC -= 2,
             }
         },
+        // Every address is checked before anything is written, so a failing instruction leaves the machine as it was
         Instruction::LDARR(end_reg) => {
-            for (i, reg) in get_v_register_range(end_reg).into_iter().enumerate() {
-                let val = register_file.get_v_register(reg);
-                let dest_addr = (register_file.I as usize) + i;
-                ram[dest_addr] = val;
+            let dest_addrs = (0..=end_reg as usize)
+                .map(|i| quirks.memory_bounds.resolve(register_file.I as usize + i))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            for (reg, dest_addr) in get_v_register_range(end_reg).into_iter().zip(dest_addrs) {
+                if let Some(dest_addr) = dest_addr {
+                    ram[dest_addr] = register_file.get_v_register(reg);
+                }
             }
         },
         Instruction::RDARR(end_reg) => {
-            for (i, reg) in get_v_register_range(end_reg).into_iter().enumerate() {
-                let src_addr = (register_file.I as usize) + i;
-                register_file.set_v_register(reg, ram[src_addr]);
+            let vals = (0..=end_reg as usize)
+                .map(|i| quirks.memory_bounds.read(ram, register_file.I as usize + i))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            for (reg, val) in get_v_register_range(end_reg).into_iter().zip(vals) {
+                register_file.set_v_register(reg, val);
             }
         },
         Instruction::LDF(reg) => {
@@ -147,13 +267,24 @@ pub fn execute_instruction(
 
             register_file.I = (RAM_DIGIT_SPRITE_START + (DIGIT_SPRITE_SIZE * (val as usize))) as u16;
         },
-        Instruction::JP(addr) => register_file.PC = addr,
+        Instruction::JP(addr) => {
+            let jump_address = register_file.PC;
+            register_file.PC = addr;
+
+            if addr == jump_address {
+                return Ok(Stall::Halted.into());
+            }
+            if is_delay_loop(ram, addr, jump_address) {
+                return Ok(Stall::Idle.into());
+            }
+        },
         Instruction::SE(reg, byte_or_reg) => {
             let reg_val = register_file.get_v_register(reg);
             let val = get_val_from_byte_or_v_register(byte_or_reg, register_file);
 
             if reg_val == val {
                 register_file.PC += 2;
+                return Ok(Outcome::skipped());
             }
         }
         Instruction::SNE(reg, byte_or_reg) => {
@@ -162,6 +293,7 @@ pub fn execute_instruction(
 
             if reg_val != val {
                 register_file.PC += 2;
+                return Ok(Outcome::skipped());
             }
         }
         Instruction::ADD(reg, byte_or_reg) => {
@@ -276,12 +408,14 @@ pub fn execute_instruction(
             let val = register_file.get_v_register(reg);
             if keyboard.is_key_pressed(val) {
                 register_file.PC += 2;
+                return Ok(Outcome::skipped());
             }
         },
         Instruction::SKNP(reg) => {
             let val = register_file.get_v_register(reg);
             if !keyboard.is_key_pressed(val) {
                 register_file.PC += 2;
+                return Ok(Outcome::skipped());
             }
         },
         Instruction::LDBCD(reg) => {
@@ -290,27 +424,152 @@ pub fn execute_instruction(
             let tens = (val - hundreds * 100) / 10;
             let ones = val - hundreds * 100 - tens * 10;
 
-            let i_val = register_file.I;
-            if (i_val + 2) as usize > ram.len() {
-                return Err(Box::new(RAMOutOfBoundsError::new()));
-            }
+            let dest_addrs = (0..3)
+                .map(|i| quirks.memory_bounds.resolve(register_file.I as usize + i))
+                .collect::<Result<Vec<_>, _>>()?;
 
-            ram[i_val as usize] = hundreds;
-            ram[(i_val + 1) as usize] = tens;
-            ram[(i_val + 2) as usize] = ones;
+            for (val, dest_addr) in [hundreds, tens, ones].iter().zip(dest_addrs) {
+                if let Some(dest_addr) = dest_addr {
+                    ram[dest_addr] = *val;
+                }
+            }
         },
         Instruction::DRW(reg_a, reg_b, n) => {
-            let mut sprite = vec![];
-            for i in 0..n {
-                let addr = register_file.I + (i as u16);
-                sprite.push(ram[addr as usize])
-            }
+            let sprite = (0..n as usize)
+                .map(|i| quirks.memory_bounds.read(ram, register_file.I as usize + i))
+                .collect::<Result<Vec<_>, _>>()?;
             let x = register_file.get_v_register(reg_a);
             let y = register_file.get_v_register(reg_b);
-            let vf_val = screen.draw(x, y, sprite)?;
+            let vf_val = screen.draw(x, y, sprite, register_file.PC, register_file.I)?;
 
             register_file.set_v_register(VRegister::VF, vf_val as u8);
+
+            if quirks.display_wait {
+                return Ok(Stall::DisplayWait.into());
+            }
         },
     };
-    Ok(())
+    Ok(Stall::None.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ execute_instruction, Outcome, Stall };
+    use crate::exec::headless::HeadlessRunner;
+    use crate::exec::quirks::Quirks;
+    use crate::io::keys::HeadlessKeyboard;
+    use crate::io::screen::Screen;
+    use crate::logic::instruction::{ ByteOrVReg, Instruction };
+    use crate::mem::register::{ RegisterFile, VRegister };
+    use crate::mem::RAM_SIZE;
+
+    // Waits for a key into V1 at 202, then stops at 204
+    const KEY_WAIT: [u8; 6] = [
+        0x61, 0x00, // 200: LD V1, 00
+        0xF1, 0x0A, // 202: LD V1, K
+        0x12, 0x04, // 204: JP 204
+    ];
+
+    fn key_wait_runner(quirks: Quirks) -> HeadlessRunner {
+        HeadlessRunner::new(KEY_WAIT.to_vec(), 1, None, quirks).unwrap()
+    }
+
+    fn steps(runner: &mut HeadlessRunner, count: usize) {
+        for _ in 0..count {
+            runner.step().unwrap();
+        }
+    }
+
+    fn assert_waiting(runner: &HeadlessRunner) {
+        assert_eq!(runner.cpu().inspect_register_file().PC, 0x202);
+    }
+
+    fn assert_key_stored(runner: &HeadlessRunner, key: u8) {
+        let registers = runner.cpu().inspect_register_file();
+        assert_eq!(registers.PC, 0x204);
+        assert_eq!(registers.get_v_register(VRegister::V1), key);
+    }
+
+    #[test]
+    fn key_wait_stores_key_on_release() {
+        let mut runner = key_wait_runner(Quirks::default());
+        steps(&mut runner, 3);
+        assert_waiting(&runner);
+
+        runner.set_key_pressed(0x7, true);
+        steps(&mut runner, 3);
+        assert_waiting(&runner);
+
+        runner.set_key_pressed(0x7, false);
+        steps(&mut runner, 1);
+        assert_key_stored(&runner, 0x7);
+    }
+
+    #[test]
+    fn key_wait_ignores_key_held_before_wait() {
+        let mut runner = key_wait_runner(Quirks::default());
+        runner.set_key_pressed(0x3, true);
+        steps(&mut runner, 3);
+        assert_waiting(&runner);
+
+        // Releasing a key pressed before the wait began does not answer it
+        runner.set_key_pressed(0x3, false);
+        steps(&mut runner, 2);
+        assert_waiting(&runner);
+
+        runner.set_key_pressed(0x9, true);
+        steps(&mut runner, 1);
+        runner.set_key_pressed(0x9, false);
+        steps(&mut runner, 1);
+        assert_key_stored(&runner, 0x9);
+    }
+
+    #[test]
+    fn key_wait_held_quirk_stores_key_on_press() {
+        let quirks: Quirks = "key-wait-held".parse().unwrap();
+
+        let mut runner = key_wait_runner(quirks);
+        steps(&mut runner, 3);
+        assert_waiting(&runner);
+
+        runner.set_key_pressed(0x4, true);
+        steps(&mut runner, 1);
+        assert_key_stored(&runner, 0x4);
+
+        // A key still held from before is taken straight away as well
+        let mut runner = key_wait_runner(quirks);
+        runner.set_key_pressed(0xA, true);
+        steps(&mut runner, 2);
+        assert_key_stored(&runner, 0xA);
+    }
+
+    fn execute_at(pc: u16, instruction: Instruction) -> (Outcome, u16) {
+        let mut registers = RegisterFile::new();
+        registers.PC = pc;
+        let outcome = execute_instruction(instruction, &mut registers, &mut [0; RAM_SIZE], &HeadlessKeyboard::new(),
+                                          &mut Screen::new(), Quirks::default(), &mut None).unwrap();
+        (outcome, registers.PC)
+    }
+
+    #[test]
+    fn skips_are_reported() {
+        let (outcome, _) = execute_at(0x200, Instruction::SE(VRegister::V0, ByteOrVReg::Byte(0)));
+        assert_eq!(outcome, Outcome { stall: Stall::None, skipped: true });
+
+        let (outcome, _) = execute_at(0x200, Instruction::SNE(VRegister::V0, ByteOrVReg::Byte(0)));
+        assert_eq!(outcome, Outcome { stall: Stall::None, skipped: false });
+
+        let (outcome, _) = execute_at(0x200, Instruction::SKNP(VRegister::V0));
+        assert!(outcome.skipped);
+    }
+
+    #[test]
+    fn short_jumps_are_not_skips() {
+        let (outcome, pc) = execute_at(0x200, Instruction::JP(0x204));
+        assert_eq!(pc, 0x204);
+        assert!(!outcome.skipped);
+
+        let (outcome, _) = execute_at(0x200, Instruction::CALL(0x204));
+        assert!(!outcome.skipped);
+    }
 }
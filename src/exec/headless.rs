@@ -0,0 +1,171 @@
+use super::cpu::CPU;
+use super::executor::Stall;
+use super::quirks::Quirks;
+use crate::io::keys::{ HeadlessKeyboard, Key };
+use crate::io::screen::Screen;
+
+use std::error::Error;
+
+pub const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 8;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Backend {
+    Interpreter,
+    // Straight-line runs of instructions are compiled into closures once and rerun from a cache
+    BlockCompiler,
+}
+
+pub struct HeadlessRunner {
+    cpu: CPU,
+    screen: Screen,
+    keyboard: HeadlessKeyboard,
+    instructions_per_frame: u32,
+    frame_instructions: u32,
+    frames: u64,
+    halted: bool,
+    backend: Backend,
+}
+
+impl HeadlessRunner {
+    pub fn new(program: Vec<u8>, instructions_per_frame: u32, trace_capacity: Option<usize>,
+               quirks: Quirks) -> Result<HeadlessRunner, Box<dyn Error>> {
+        let mut cpu = CPU::new(trace_capacity);
+        cpu.set_quirks(quirks);
+        cpu.map_program(program)?;
+        cpu.map_digit_sprites();
+
+        Ok(HeadlessRunner {
+            cpu,
+            screen: Screen::new(),
+            keyboard: HeadlessKeyboard::new(),
+            instructions_per_frame: instructions_per_frame.max(1),
+            frame_instructions: 0,
+            frames: 0,
+            halted: false,
+            backend: Backend::Interpreter,
+        })
+    }
+
+    pub fn set_backend(&mut self, backend: Backend) {
+        self.backend = backend;
+    }
+
+    pub fn step(&mut self) -> Result<Stall, Box<dyn Error>> {
+        let stall = self.cpu.execute_cycle(self.keyboard, &mut self.screen)?;
+        self.count_instructions(1, stall);
+
+        Ok(stall)
+    }
+
+    // Runs one compiled block, never past the end of the frame, and returns how many instructions ran
+    pub fn run_block(&mut self) -> Result<u32, Box<dyn Error>> {
+        let remaining = self.instructions_per_frame - self.frame_instructions;
+        let (executed, stall) = self.cpu.execute_block(self.keyboard, &mut self.screen, remaining)?;
+        self.count_instructions(executed, stall);
+
+        Ok(executed)
+    }
+
+    // Stalls end the frame early, like the scheduler does for the other frontends
+    fn count_instructions(&mut self, executed: u32, stall: Stall) {
+        self.frame_instructions += executed;
+        if stall == Stall::Halted {
+            self.halted = true;
+        }
+
+        if self.frame_instructions >= self.instructions_per_frame || stall != Stall::None {
+            self.frame_instructions = 0;
+            self.frames += 1;
+            self.cpu.tick_timers();
+            self.screen.tick_frame();
+        }
+    }
+
+    pub fn run_frame(&mut self) -> Result<(), Box<dyn Error>> {
+        let frame = self.frames;
+        while self.frames == frame {
+            match self.backend {
+                Backend::Interpreter => {
+                    self.step()?;
+                },
+                Backend::BlockCompiler => {
+                    self.run_block()?;
+                },
+            };
+        }
+
+        Ok(())
+    }
+
+    pub fn set_instruction_cache(&mut self, enabled: bool) {
+        self.cpu.set_instruction_cache(enabled);
+    }
+
+    pub fn set_key_pressed(&mut self, key: Key, pressed: bool) {
+        self.keyboard.set_key_pressed(key, pressed);
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    // Jumped to itself, nothing but the timers changes from here on
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    pub fn screen(&self) -> &Screen {
+        &self.screen
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HeadlessRunner;
+    use crate::exec::executor::Stall;
+    use crate::exec::quirks::Quirks;
+
+    // Waits for DT to run out in a delay loop, then stops
+    const DELAY_THEN_HALT: [u8; 12] = [
+        0x60, 0x02, // 200: LD V0, 02
+        0xF0, 0x15, // 202: LD DT, V0
+        0xF0, 0x07, // 204: LD V0, DT
+        0x30, 0x00, // 206: SE V0, 00
+        0x12, 0x04, // 208: JP 204
+        0x12, 0x0A, // 20A: JP 20A
+    ];
+
+    fn runner() -> HeadlessRunner {
+        HeadlessRunner::new(DELAY_THEN_HALT.to_vec(), 100, None, Quirks::default()).unwrap()
+    }
+
+    #[test]
+    fn delay_loops_end_the_frame() {
+        let mut runner = runner();
+        for _ in 0..4 {
+            assert_eq!(runner.step().unwrap(), Stall::None);
+        }
+
+        assert_eq!(runner.step().unwrap(), Stall::Idle);
+        assert_eq!(runner.frames(), 1);
+        assert_eq!(runner.cpu().inspect_register_file().DT, 1);
+    }
+
+    #[test]
+    fn halting_ends_the_frame_and_is_reported() {
+        let mut runner = runner();
+        while !runner.halted() {
+            runner.run_frame().unwrap();
+            assert!(runner.frames() <= 3, "the delay loop ran past DT");
+        }
+
+        assert_eq!(runner.cpu().inspect_register_file().PC, 0x20A);
+        let frames = runner.frames();
+        assert_eq!(runner.step().unwrap(), Stall::Halted);
+        assert_eq!(runner.frames(), frames + 1);
+    }
+}
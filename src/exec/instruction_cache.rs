@@ -0,0 +1,61 @@
+use super::executor::STACK_FRAME_SIZE;
+use crate::logic::decoder::{ self, MalformedInstructionError };
+use crate::logic::instruction::Instruction;
+use crate::mem::register::RegisterFile;
+use crate::mem::RAM_SIZE;
+
+use std::ops::Range;
+
+// Instructions decoded once per address, so loops do not go through the decoder on every cycle
+pub struct InstructionCache {
+    instructions: Vec<Option<Instruction>>,
+}
+
+impl Default for InstructionCache {
+    fn default() -> InstructionCache {
+        InstructionCache::new()
+    }
+}
+
+impl InstructionCache {
+    pub fn new() -> InstructionCache {
+        InstructionCache {
+            instructions: vec![None; RAM_SIZE],
+        }
+    }
+
+    pub fn decode(&mut self, address: usize, msb: u8, lsb: u8) -> Result<Instruction, MalformedInstructionError> {
+        if let Some(instruction) = self.instructions[address] {
+            return Ok(instruction);
+        }
+
+        let instruction = decoder::decode_instruction(msb, lsb)?;
+        self.instructions[address] = Some(instruction);
+        Ok(instruction)
+    }
+
+    // The instruction starting one byte before a write also contains the written byte
+    pub fn invalidate(&mut self, written: Range<usize>) {
+        let start = written.start.saturating_sub(1).min(RAM_SIZE);
+        let end = written.end.min(RAM_SIZE);
+
+        for instruction in &mut self.instructions[start..end.max(start)] {
+            *instruction = None;
+        }
+    }
+}
+
+// Bytes of RAM an instruction writes to, given the registers before it ran
+pub fn ram_writes(instruction: Instruction, registers: &RegisterFile) -> Option<Range<usize>> {
+    let i = registers.I as usize;
+
+    match instruction {
+        Instruction::LDARR(x) => Some(i..i + x as usize + 1),
+        Instruction::LDBCD(_) => Some(i..i + 3),
+        Instruction::CALL(_) => {
+            let sp = registers.SP as usize;
+            Some(sp..sp + STACK_FRAME_SIZE)
+        },
+        _ => None,
+    }
+}
@@ -1,3 +1,14 @@
+pub mod block_compiler;
 pub mod cpu;
+pub mod error;
 pub mod executor;
+pub mod instruction_cache;
+pub mod quirks;
 pub mod runtime;
+pub mod scheduler;
+pub mod vip_timing;
+pub mod trace;
+pub mod headless;
+pub mod trace_diff;
+pub mod rom_test;
+pub mod terminal_debugger;
@@ -0,0 +1,85 @@
+use super::cpu::RAMOutOfBoundsError;
+use crate::mem::{RAM, RAM_SIZE};
+
+use std::error::Error;
+use std::str::FromStr;
+
+pub const QUIRK_NAMES: [&str; 4] = ["key-wait-held", "display-wait", "memory-wrap", "memory-ignore"];
+
+#[derive(Debug)]
+pub struct InvalidQuirkError {
+    quirk: String,
+}
+
+impl std::fmt::Display for InvalidQuirkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Unknown quirk {}, expected one of {}", self.quirk, QUIRK_NAMES.join(", "))
+    }
+}
+
+impl Error for InvalidQuirkError {}
+
+// What happens when an instruction reads or writes past the end of RAM
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MemoryBounds {
+    // The instruction fails with a RAMOutOfBoundsError
+    Error,
+    // Addresses wrap around at 4 KiB
+    Wrap,
+    // Reads give 0 and writes are dropped
+    Ignore,
+}
+
+impl Default for MemoryBounds {
+    fn default() -> MemoryBounds {
+        MemoryBounds::Error
+    }
+}
+
+impl MemoryBounds {
+    // The RAM index an access to address ends up at, None when the access is dropped
+    pub fn resolve(self, address: usize) -> Result<Option<usize>, RAMOutOfBoundsError> {
+        if address < RAM_SIZE {
+            return Ok(Some(address));
+        }
+
+        match self {
+            MemoryBounds::Error => Err(RAMOutOfBoundsError::new()),
+            MemoryBounds::Wrap => Ok(Some(address % RAM_SIZE)),
+            MemoryBounds::Ignore => Ok(None),
+        }
+    }
+
+    pub fn read(self, ram: &RAM, address: usize) -> Result<u8, RAMOutOfBoundsError> {
+        Ok(self.resolve(address)?.map_or(0, |address| ram[address]))
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Quirks {
+    // Fx0A returns as soon as any key is held instead of waiting for a fresh press to be released
+    pub key_wait_held: bool,
+    // DXYN waits for the next display interrupt like the VIP interpreter, limiting sprites to 60 per second
+    pub display_wait: bool,
+    pub memory_bounds: MemoryBounds,
+}
+
+impl FromStr for Quirks {
+    type Err = InvalidQuirkError;
+
+    fn from_str(s: &str) -> Result<Quirks, InvalidQuirkError> {
+        let mut quirks = Quirks::default();
+
+        for quirk in s.split(',').map(|quirk| quirk.trim()).filter(|quirk| !quirk.is_empty()) {
+            match quirk {
+                "key-wait-held" => quirks.key_wait_held = true,
+                "display-wait" => quirks.display_wait = true,
+                "memory-wrap" => quirks.memory_bounds = MemoryBounds::Wrap,
+                "memory-ignore" => quirks.memory_bounds = MemoryBounds::Ignore,
+                _ => return Err(InvalidQuirkError { quirk: quirk.to_string() }),
+            };
+        }
+
+        Ok(quirks)
+    }
+}
@@ -0,0 +1,244 @@
+use super::headless::HeadlessRunner;
+use super::quirks::Quirks;
+use crate::io::image::{RgbImage, MONOCHROME_OFF, MONOCHROME_ON};
+use crate::io::keys::Key;
+use crate::io::screen::{Screen, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+use std::error::Error;
+use std::str::FromStr;
+
+pub const DIFF_IMAGE_SCALE: u32 = 8;
+pub const DIFF_MISSING_COLOR: [u8; 3] = [255, 0, 0];
+pub const DIFF_EXTRA_COLOR: [u8; 3] = [0, 255, 0];
+
+#[derive(Debug)]
+pub struct InvalidKeyPressError {
+    press: String,
+}
+
+impl std::fmt::Display for InvalidKeyPressError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid key press {}, expected KEY@FRAME or KEY@START-END", self.press)
+    }
+}
+
+impl Error for InvalidKeyPressError {}
+
+#[derive(Debug)]
+pub struct InvalidGoldenImageError {
+    width: u32,
+    height: u32,
+}
+
+impl std::fmt::Display for InvalidGoldenImageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Golden image is {}x{}, expected an integer multiple of {}x{}",
+            self.width, self.height, SCREEN_WIDTH, SCREEN_HEIGHT
+        )
+    }
+}
+
+impl Error for InvalidGoldenImageError {}
+
+#[derive(Debug, Copy, Clone)]
+pub struct KeyPress {
+    pub key: Key,
+    pub start_frame: u64,
+    pub end_frame: u64,
+}
+
+impl KeyPress {
+    pub fn is_held_at(&self, frame: u64) -> bool {
+        frame >= self.start_frame && frame <= self.end_frame
+    }
+}
+
+impl FromStr for KeyPress {
+    type Err = InvalidKeyPressError;
+
+    fn from_str(s: &str) -> Result<KeyPress, InvalidKeyPressError> {
+        let error = || InvalidKeyPressError { press: s.to_string() };
+
+        let mut parts = s.splitn(2, '@');
+        let key = parts.next().ok_or_else(error)?;
+        let frames = parts.next().ok_or_else(error)?;
+
+        let key = u8::from_str_radix(key, 16).map_err(|_| error())?;
+        if key > 0xF {
+            return Err(error());
+        }
+
+        let mut frames = frames.splitn(2, '-');
+        let start_frame: u64 = frames.next().ok_or_else(error)?.parse().map_err(|_| error())?;
+        let end_frame: u64 = match frames.next() {
+            Some(end) => end.parse().map_err(|_| error())?,
+            None => start_frame,
+        };
+        if end_frame < start_frame {
+            return Err(error());
+        }
+
+        Ok(KeyPress {
+            key,
+            start_frame,
+            end_frame,
+        })
+    }
+}
+
+pub type Pixels = [[bool; SCREEN_WIDTH]; SCREEN_HEIGHT];
+
+pub fn pixels_from_golden(golden: &RgbImage) -> Result<Pixels, InvalidGoldenImageError> {
+    let scale = golden.width / SCREEN_WIDTH as u32;
+    if scale == 0 || golden.width != scale * SCREEN_WIDTH as u32 || golden.height != scale * SCREEN_HEIGHT as u32 {
+        return Err(InvalidGoldenImageError {
+            width: golden.width,
+            height: golden.height,
+        });
+    }
+
+    let mut pixels = [[false; SCREEN_WIDTH]; SCREEN_HEIGHT];
+    for (row, row_arr) in pixels.iter_mut().enumerate() {
+        for (column, pixel) in row_arr.iter_mut().enumerate() {
+            let [r, g, b] = golden.get_pixel(column as u32 * scale + scale / 2, row as u32 * scale + scale / 2);
+            let luminance = (r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000;
+            *pixel = luminance >= 128;
+        }
+    }
+
+    Ok(pixels)
+}
+
+pub fn count_mismatches(screen: &Screen, expected: &Pixels) -> usize {
+    screen
+        .inspect_screen()
+        .iter()
+        .zip(expected.iter())
+        .map(|(actual_row, expected_row)| {
+            actual_row.iter().zip(expected_row.iter()).filter(|(actual, expected)| actual != expected).count()
+        })
+        .sum()
+}
+
+pub fn render_diff(screen: &Screen, expected: &Pixels) -> RgbImage {
+    let mut image = RgbImage::new(SCREEN_WIDTH as u32 * DIFF_IMAGE_SCALE, SCREEN_HEIGHT as u32 * DIFF_IMAGE_SCALE);
+
+    for (row, row_arr) in screen.inspect_screen().iter().enumerate() {
+        for (column, actual) in row_arr.iter().enumerate() {
+            let color = match (*actual, expected[row][column]) {
+                (true, true) => MONOCHROME_ON,
+                (false, false) => MONOCHROME_OFF,
+                (false, true) => DIFF_MISSING_COLOR,
+                (true, false) => DIFF_EXTRA_COLOR,
+            };
+            image.fill_cell(column, row, DIFF_IMAGE_SCALE, color);
+        }
+    }
+
+    image
+}
+
+pub fn run_rom_test(program: Vec<u8>, frames: u64, instructions_per_frame: u32,
+                    key_presses: &[KeyPress], quirks: Quirks) -> Result<HeadlessRunner, Box<dyn Error>> {
+    let mut runner = HeadlessRunner::new(program, instructions_per_frame, None, quirks)?;
+
+    while runner.frames() < frames {
+        let frame = runner.frames();
+        for key in 0x0..(0xF + 1) {
+            let held = key_presses.iter().any(|press| press.key == key && press.is_held_at(frame));
+            runner.set_key_pressed(key, held);
+        }
+
+        runner.run_frame()?;
+
+        // Only the timers change once the program has jumped to itself
+        if runner.halted() {
+            break;
+        }
+    }
+
+    Ok(runner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ count_mismatches, pixels_from_golden, render_diff, KeyPress };
+    use super::{ DIFF_EXTRA_COLOR, DIFF_IMAGE_SCALE, DIFF_MISSING_COLOR };
+    use crate::io::image::{ render_screen, RgbImage, MONOCHROME_OFF, MONOCHROME_ON };
+    use crate::io::screen::{ Screen, SCREEN_HEIGHT, SCREEN_WIDTH };
+
+    fn key_press(press: &str) -> (u8, u64, u64) {
+        let press: KeyPress = press.parse().unwrap();
+        (press.key, press.start_frame, press.end_frame)
+    }
+
+    // A 4 pixel wide bar in the top left corner
+    fn screen_with_bar() -> Screen {
+        let mut screen = Screen::new();
+        screen.draw(0, 0, vec![0xF0], 0x200, 0x000).unwrap();
+        screen
+    }
+
+    #[test]
+    fn parses_key_presses() {
+        assert_eq!(key_press("a@5"), (0xA, 5, 5));
+        assert_eq!(key_press("F@10-20"), (0xF, 10, 20));
+        assert_eq!(key_press("0@0-0"), (0x0, 0, 0));
+
+        let press: KeyPress = "3@10-12".parse().unwrap();
+        assert!(!press.is_held_at(9));
+        assert!(press.is_held_at(10));
+        assert!(press.is_held_at(12));
+        assert!(!press.is_held_at(13));
+    }
+
+    #[test]
+    fn rejects_malformed_key_presses() {
+        for press in ["", "5", "5@", "@5", "G@5", "10@5", "5@x", "5@-3", "5@3-", "5@10-3"].iter() {
+            assert!(press.parse::<KeyPress>().is_err(), "{} was accepted", press);
+        }
+    }
+
+    #[test]
+    fn golden_images_are_read_at_any_integer_scale() {
+        let screen = screen_with_bar();
+
+        for &scale in [1, 3, 10].iter() {
+            let golden = render_screen(&screen, scale, MONOCHROME_ON, MONOCHROME_OFF);
+            let pixels = pixels_from_golden(&golden).unwrap();
+            assert_eq!(count_mismatches(&screen, &pixels), 0);
+            assert_eq!(pixels[0][..5], [true, true, true, true, false]);
+        }
+
+        let golden = render_screen(&screen, 2, MONOCHROME_ON, MONOCHROME_OFF);
+        assert_eq!(count_mismatches(&Screen::new(), &pixels_from_golden(&golden).unwrap()), 4);
+    }
+
+    #[test]
+    fn golden_images_of_other_sizes_are_rejected() {
+        let width = SCREEN_WIDTH as u32;
+        let height = SCREEN_HEIGHT as u32;
+
+        let sizes = [(0, 0), (width - 1, height), (width, height + 1), (2 * width, height), (3 * width + 1, 3 * height)];
+        for &(golden_width, golden_height) in sizes.iter() {
+            assert!(pixels_from_golden(&RgbImage::new(golden_width, golden_height)).is_err(),
+                    "{}x{} was accepted", golden_width, golden_height);
+        }
+    }
+
+    #[test]
+    fn diff_marks_missing_and_extra_pixels() {
+        let mut expected = [[false; SCREEN_WIDTH]; SCREEN_HEIGHT];
+        expected[0][1] = true;
+        expected[1][0] = true;
+
+        let diff = render_diff(&screen_with_bar(), &expected);
+        let cell = |column: u32, row: u32| diff.get_pixel(column * DIFF_IMAGE_SCALE, row * DIFF_IMAGE_SCALE);
+        assert_eq!(cell(0, 0), DIFF_EXTRA_COLOR);
+        assert_eq!(cell(1, 0), MONOCHROME_ON);
+        assert_eq!(cell(0, 1), DIFF_MISSING_COLOR);
+        assert_eq!(cell(1, 1), MONOCHROME_OFF);
+    }
+}
@@ -1,20 +1,37 @@
 use super::cpu::CPU;
+use super::quirks::Quirks;
+use super::error::{ save_crash_dump, EmulatorError };
+use super::executor::Stall;
+use super::scheduler::{ Scheduler, Speed, Timing };
+use super::terminal_debugger::{ DebuggerCommand, TerminalDebugger };
+use super::trace::{ TraceFilter, TraceFormat, TracePage, DEFAULT_TRACE_CAPACITY };
 use crate::logic::instruction::Instruction;
 use crate::mem::register::RegisterFile;
-use crate::io::keys::SDL2Keyboard;
-use crate::io::screen::Screen;
+use crate::io::frontend::{ Frontend, FrontendEvent };
+use crate::io::terminal_frontend::TerminalFrontend;
+use crate::io::keymap::{ KeyMap, DEFAULT_LAYOUT };
+use crate::io::sdl_frontend::{ SdlFrontend, DEFAULT_WINDOW_SCALE };
+use crate::io::palette::Palette;
+use crate::io::display_filter::{ DisplayFilter, DisplayFilterKind };
+use crate::io::screen::{ Screen, ScreenSnapshot, PixelProvenance };
+use crate::io::image::{ render_screen, save_screenshot, Rgb, MAX_SCREENSHOT_SCALE };
+use crate::io::recording::{ Recorder, RecordingFormat, RecordingSettings, DEFAULT_RECORDING_SCALE };
 
-use sdl2;
-use sdl2::event::Event;
 use rocket;
 use rocket::State;
+use rocket::http::ContentType;
+use rocket::response::content::Content;
 use rocket_contrib::serve::StaticFiles;
 use rocket_contrib::json::Json;
 
-use std::time::{ Instant, Duration };
+use std::time::Duration;
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::collections::HashSet;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{ Path, PathBuf };
+use std::time::{ SystemTime, UNIX_EPOCH };
 
 #[get("/registers")]
 fn registers(cpu_lock: State<Arc<Mutex<CPU>>>) -> Json<RegisterFile> {
@@ -28,6 +45,12 @@ fn memory(cpu_lock: State<Arc<Mutex<CPU>>>) -> Json<Vec<u8>> {
     Json(cpu.inspect_memory().to_vec())
 }
 
+#[get("/stack")]
+fn stack(cpu_lock: State<Arc<Mutex<CPU>>>) -> Json<Vec<u16>> {
+    let cpu = cpu_lock.lock().unwrap();
+    Json(cpu.inspect_stack())
+}
+
 #[post("/add-pc-breakpoint/<pc>")]
 fn add_pc_breakpoint(pc: u16, breakpoints_lock: State<Arc<Mutex<HashSet<u16>>>>) {
     let mut breakpoints = breakpoints_lock.lock().unwrap();
@@ -109,6 +132,60 @@ fn last_draw_result(screen_lock: State<Arc<Mutex<Screen>>>) -> Json<Option<Vec<u
     Json(screen.inspect_last_draw_result())
 }
 
+#[get("/pixel-provenance/<x>/<y>")]
+fn pixel_provenance(x: usize, y: usize, screen_lock: State<Arc<Mutex<Screen>>>) -> Json<Option<PixelProvenance>> {
+    let screen = screen_lock.lock().unwrap();
+    Json(screen.inspect_pixel_provenance(x, y))
+}
+
+#[get("/screen-snapshot")]
+fn screen_snapshot(screen_lock: State<Arc<Mutex<Screen>>>) -> Json<ScreenSnapshot> {
+    let screen = screen_lock.lock().unwrap();
+    Json(screen.snapshot())
+}
+
+pub struct ScreenshotSettings {
+    scale: u32,
+    directory: PathBuf,
+    palette: Arc<Mutex<Palette>>,
+}
+
+// Oversized scales from the query string are turned away before they can overflow while the screen is locked
+fn requested_scale(scale: Option<u32>, settings: &ScreenshotSettings) -> Option<u32> {
+    Some(scale.unwrap_or(settings.scale)).filter(|scale| *scale <= MAX_SCREENSHOT_SCALE)
+}
+
+#[get("/screenshot.png?<scale>")]
+fn screenshot(scale: Option<u32>, screen_lock: State<Arc<Mutex<Screen>>>,
+              settings: State<ScreenshotSettings>) -> Option<Content<Vec<u8>>> {
+    let scale = requested_scale(scale, &settings)?;
+    let screen = screen_lock.lock().unwrap();
+    let palette = settings.palette.lock().unwrap();
+    let image = render_screen(&screen, scale, palette.foreground(), palette.background());
+
+    let mut png = vec![];
+    image.write_png(&mut png).ok()?;
+    Some(Content(ContentType::PNG, png))
+}
+
+#[post("/screenshot?<scale>")]
+fn save_screenshot_route(scale: Option<u32>, screen_lock: State<Arc<Mutex<Screen>>>,
+                         settings: State<ScreenshotSettings>) -> Json<Option<String>> {
+    let scale = match requested_scale(scale, &settings) {
+        Some(scale) => scale,
+        None => return Json(None),
+    };
+    let screen = screen_lock.lock().unwrap();
+    let palette = settings.palette.lock().unwrap();
+    match save_screenshot(&screen, &settings.directory, scale, palette.foreground(), palette.background()) {
+        Ok(path) => Json(Some(path.display().to_string())),
+        Err(error) => {
+            println!("Error while saving screenshot: {}", error);
+            Json(None)
+        },
+    }
+}
+
 #[post("/step-next-draw")]
 fn step_next_draw(step_next_draw_lock: State<Arc<Mutex<CanStepNext>>>) {
     let mut step_next_draw = step_next_draw_lock.lock().unwrap();
@@ -130,45 +207,240 @@ fn next_instruction(cpu_lock: State<Arc<Mutex<CPU>>>) -> Json<Option<Instruction
     }
 }
 
-#[get("/instruction-trace")]
-fn instruction_trace(cpu_lock: State<Arc<Mutex<CPU>>>) -> Json<Option<Vec<(u16, Instruction)>>> {
+pub const DEFAULT_TRACE_PAGE_SIZE: usize = 256;
+
+fn parse_trace_filter(pc_start: Option<u16>, pc_end: Option<u16>, kinds: Option<String>) -> TraceFilter {
+    TraceFilter {
+        pc_start,
+        pc_end,
+        kinds: kinds.map(|kinds| kinds.split(',').map(|kind| kind.trim().to_string()).collect()),
+    }
+}
+
+#[get("/instruction-trace?<offset>&<limit>&<pc_start>&<pc_end>&<kinds>")]
+fn instruction_trace(offset: Option<usize>, limit: Option<usize>, pc_start: Option<u16>, pc_end: Option<u16>,
+                     kinds: Option<String>, cpu_lock: State<Arc<Mutex<CPU>>>) -> Json<Option<TracePage>> {
     let cpu = cpu_lock.lock().unwrap();
-    Json(cpu.get_instruction_trace())
+    let filter = parse_trace_filter(pc_start, pc_end, kinds);
+    Json(cpu.inspect_instruction_trace().map(|trace| {
+        trace.page(&filter, offset.unwrap_or(0), limit.unwrap_or(DEFAULT_TRACE_PAGE_SIZE))
+    }))
 }
 
-pub struct Runtime {}
+#[get("/instruction-trace/export?<format>&<pc_start>&<pc_end>&<kinds>")]
+fn export_instruction_trace(format: Option<String>, pc_start: Option<u16>, pc_end: Option<u16>,
+                            kinds: Option<String>, cpu_lock: State<Arc<Mutex<CPU>>>) -> Option<String> {
+    let format: TraceFormat = format.unwrap_or_else(|| "text".to_string()).parse().ok()?;
+    let filter = parse_trace_filter(pc_start, pc_end, kinds);
 
-pub const TIMER_HZ: f64 = 60.0;
+    let cpu = cpu_lock.lock().unwrap();
+    let trace = cpu.inspect_instruction_trace()?;
 
-pub const ON_COLOR: sdl2::pixels::Color = sdl2::pixels::Color::RGBA(186, 109, 0, 255);
-pub const OFF_COLOR: sdl2::pixels::Color = sdl2::pixels::Color::RGBA(255, 164, 36, 255);
+    let mut exported = vec![];
+    trace.export(&mut exported, &filter, format).ok()?;
+    String::from_utf8(exported).ok()
+}
 
-fn draw_to_canvas(canvas: &mut sdl2::render::WindowCanvas, screen: &Screen) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    for (row, row_arr) in screen.inspect_screen().iter().enumerate() {
-        for (column, pixel) in row_arr.iter().enumerate() {
-            if *pixel {
-                canvas.set_draw_color(ON_COLOR);
-                canvas.draw_point(sdl2::rect::Point::new(column as i32, row as i32))?;
-            } else {
-                canvas.set_draw_color(OFF_COLOR);
-                canvas.draw_point(sdl2::rect::Point::new(column as i32, row as i32))?;
-            }
+fn export_trace(frontend: &mut dyn Frontend, cpu: &CPU, trace_export: &Option<(PathBuf, TraceFormat)>) {
+    let (path, format) = match trace_export {
+        Some(v) => v,
+        None => return,
+    };
+
+    let trace = match cpu.inspect_instruction_trace() {
+        Some(trace) => trace,
+        None => return,
+    };
+
+    let result = File::create(path).and_then(|file| {
+        trace.export(&mut BufWriter::new(file), &TraceFilter::default(), *format)
+    });
+
+    match result {
+        Ok(_) => {},
+        Err(error) => frontend.show_message(&format!("Error while exporting instruction trace: {}", error)),
+    }
+}
+
+fn write_crash_dump(frontend: &mut dyn Frontend, cpu: &CPU, error: &EmulatorError, directory: &Path) {
+    match save_crash_dump(cpu, error, directory) {
+        Ok(path) => frontend.show_message(&format!("Saved crash dump to {}", path.display())),
+        Err(error) => frontend.show_message(&format!("Error while saving crash dump: {}", error)),
+    }
+}
+
+fn take_screenshot(frontend: &mut dyn Frontend, screen: &Screen, scale: u32, directory: &PathBuf, palette: &Palette) {
+    match save_screenshot(screen, directory, scale, palette.foreground(), palette.background()) {
+        Ok(path) => frontend.show_message(&format!("Saved screenshot to {}", path.display())),
+        Err(error) => frontend.show_message(&format!("Error while saving screenshot: {}", error)),
+    }
+}
+
+fn start_recording(frontend: &mut dyn Frontend, path: &PathBuf, settings: RecordingSettings) -> Option<Recorder> {
+    match Recorder::start(path, settings) {
+        Ok(recorder) => {
+            frontend.show_message(&format!("Recording to {}", path.display()));
+            Some(recorder)
+        },
+        Err(error) => {
+            frontend.show_message(&format!("Error while starting recording: {}", error));
+            None
+        },
+    }
+}
+
+fn stop_recording(frontend: &mut dyn Frontend, recorder: Recorder) {
+    match recorder.finish() {
+        Ok(frames) => frontend.show_message(&format!("Stopped recording after {} frames", frames)),
+        Err(error) => frontend.show_message(&format!("Error while finishing recording: {}", error)),
+    }
+}
+
+fn toggle_recording(frontend: &mut dyn Frontend, recorder: &mut Option<Recorder>, settings: RecordingSettings, directory: &PathBuf) {
+    match recorder.take() {
+        Some(active) => stop_recording(frontend, active),
+        None => {
+            let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|t| t.as_millis()).unwrap_or(0);
+            let path = directory.join(format!("rip8-{}.{}", timestamp, settings.format.extension()));
+            *recorder = start_recording(frontend, &path, settings);
+        },
+    }
+}
+
+fn present_screen(frontend: &mut dyn Frontend, display_filter: &DisplayFilter, screen: &Screen) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    frontend.present(&display_filter.frame(screen))
+}
+
+fn cycle_palette(frontend: &mut dyn Frontend, palette: &mut Palette, display_filter: &DisplayFilter,
+                 screen: &Screen) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    *palette = palette.next();
+    frontend.set_palette(palette);
+    present_screen(frontend, display_filter, screen)
+}
+
+fn cycle_display_filter(frontend: &mut dyn Frontend, display_filter: &mut DisplayFilter,
+                        screen: &Screen) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    display_filter.cycle();
+    present_screen(frontend, display_filter, screen)
+}
+
+// Records what is presented, so the display filter and palette changes show up in the recording
+fn record_frame(frontend: &mut dyn Frontend, recorder: &mut Option<Recorder>, display_filter: &DisplayFilter, screen: &Screen, palette: &Palette) {
+    if let Some(active) = recorder {
+        if let Err(error) = active.record_frame(&display_filter.frame(screen), palette) {
+            frontend.show_message(&format!("Error while recording, stopping: {}", error));
+            *recorder = None;
         }
     }
-    Ok(())
 }
 
+pub const DEFAULT_SCREENSHOT_SCALE: u32 = 10;
+
+pub struct Runtime {
+    trace_capacity: Option<usize>,
+    trace_export: Option<(PathBuf, TraceFormat)>,
+    screenshot_scale: u32,
+    screenshot_directory: PathBuf,
+    recording_settings: RecordingSettings,
+    recording_path: Option<PathBuf>,
+    key_map: KeyMap,
+    quirks: Quirks,
+    palette: Palette,
+    display_filter: DisplayFilter,
+    window_scale: u32,
+    fast_forward_speed: Speed,
+}
+
+
+
 impl Runtime {
     pub fn new() -> Runtime {
-        Runtime{}
+        Runtime{
+            trace_capacity: None,
+            trace_export: None,
+            screenshot_scale: DEFAULT_SCREENSHOT_SCALE,
+            screenshot_directory: PathBuf::from("."),
+            recording_settings: RecordingSettings {
+                format: RecordingFormat::Gif,
+                scale: DEFAULT_RECORDING_SCALE,
+                colors: None,
+            },
+            recording_path: None,
+            key_map: KeyMap::layout(DEFAULT_LAYOUT).unwrap(),
+            quirks: Quirks::default(),
+            palette: Palette::default(),
+            display_filter: DisplayFilter::new(DisplayFilterKind::Vblank),
+            window_scale: DEFAULT_WINDOW_SCALE,
+            fast_forward_speed: Speed::Uncapped,
+        }
+    }
+
+    pub fn set_display_filter(&mut self, display_filter: DisplayFilter) {
+        self.display_filter = display_filter;
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn set_key_map(&mut self, key_map: KeyMap) {
+        self.key_map = key_map;
+    }
+
+    pub fn set_window_scale(&mut self, scale: u32) {
+        self.window_scale = scale;
+    }
+
+    pub fn set_fast_forward_speed(&mut self, speed: Speed) {
+        self.fast_forward_speed = speed;
+    }
+
+    pub fn set_recording_format(&mut self, format: RecordingFormat) {
+        self.recording_settings.format = format;
+    }
+
+    pub fn set_recording_scale(&mut self, scale: u32) {
+        self.recording_settings.scale = scale;
+    }
+
+    pub fn set_recording_colors(&mut self, on_color: Rgb, off_color: Rgb) {
+        self.recording_settings.colors = Some((on_color, off_color));
+    }
+
+    pub fn record_on_start(&mut self, path: PathBuf) {
+        self.recording_path = Some(path);
+    }
+
+    pub fn set_screenshot_scale(&mut self, scale: u32) {
+        self.screenshot_scale = scale;
+    }
+
+    pub fn set_screenshot_directory(&mut self, directory: PathBuf) {
+        self.screenshot_directory = directory;
+    }
+
+    pub fn set_trace_capacity(&mut self, capacity: usize) {
+        self.trace_capacity = Some(capacity);
+    }
+
+    pub fn export_trace_on_exit(&mut self, path: PathBuf, format: TraceFormat) {
+        self.trace_export = Some((path, format));
     }
 
-    pub fn start_debug(&mut self, program: Vec<u8>, cpu_clock_speed: f64) -> Result<(), Box<dyn::std::error::Error>> {
-        let cpu_lock = Arc::new(Mutex::new(CPU::new(true)));
+    pub fn start_debug(&mut self, program: Vec<u8>, timing: Timing) -> Result<(), Box<dyn::std::error::Error>> {
+        let trace_capacity = self.trace_capacity.unwrap_or(DEFAULT_TRACE_CAPACITY);
+        let cpu_lock = Arc::new(Mutex::new(CPU::new(Some(trace_capacity))));
+        cpu_lock.lock().unwrap().set_quirks(self.quirks);
         let breakpoints_lock = Arc::new(Mutex::new(HashSet::<u16>::new()));
         let paused_lock = Arc::new(Mutex::new(IsPaused::Paused));
         let can_step_next_lock = Arc::new(Mutex::new(CanStepNext::StayPaused));
         let screen_lock = Arc::new(Mutex::new(Screen::new()));
+        screen_lock.lock().unwrap().enable_pixel_provenance();
+        let palette_lock = Arc::new(Mutex::new(self.palette.clone()));
 
         {
             let cpu_lock = cpu_lock.clone();
@@ -176,20 +448,19 @@ impl Runtime {
             let paused_lock = paused_lock.clone();
             let can_step_next_lock = can_step_next_lock.clone();
             let screen_lock = screen_lock.clone();
+            let trace_export = self.trace_export.clone();
+            let screenshot_scale = self.screenshot_scale;
+            let screenshot_directory = self.screenshot_directory.clone();
+            let recording_settings = self.recording_settings;
+            let recording_path = self.recording_path.clone();
+            let key_map = self.key_map.clone();
+            let window_scale = self.window_scale;
+            let fast_forward_speed = self.fast_forward_speed;
+            let palette_lock = palette_lock.clone();
+            let mut display_filter = self.display_filter.clone();
 
             thread::spawn(move || -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-                let sdl_context = sdl2::init()?;
-                let mut event_pump = sdl_context.event_pump()?;
-
-                let video_subsystem = sdl_context.video()?;
-
-                let window = video_subsystem.window("rip8", 1280, 640).build()?;
-                let mut canvas = window.into_canvas().build()?;
-
-                canvas.set_draw_color(OFF_COLOR);
-                canvas.clear();
-                canvas.set_scale(20.0, 20.0)?;
-                canvas.present();
+                let mut frontend = SdlFrontend::new(&palette_lock.lock().unwrap(), &key_map, window_scale)?;
 
                 {
                     let mut cpu = cpu_lock.lock().unwrap();
@@ -197,35 +468,69 @@ impl Runtime {
                     cpu.map_digit_sprites();
                 }
 
-                let cpu_time_step: Duration = Duration::new(0, (1000000000.0 / cpu_clock_speed) as u32);
+                let mut recorder = recording_path.and_then(|path| start_recording(&mut frontend, &path, recording_settings));
 
-                let mut last_frame_time = Instant::now();
-                let mut last_timer_tick = Instant::now();
+                let mut scheduler = Scheduler::new(timing);
+                scheduler.set_fast_forward_speed(fast_forward_speed);
 
                 'running: loop {
-                    let timer_ticks = ((Instant::now() - last_timer_tick).as_secs_f64() * TIMER_HZ) as u32;
+                    let timer_ticks = scheduler.timer_ticks();
                     if timer_ticks > 0 {
                         let mut cpu = cpu_lock.lock().unwrap();
-                        last_timer_tick = Instant::now();
+                        let mut screen = screen_lock.lock().unwrap();
                         for _ in 0..timer_ticks {
                             cpu.tick_timers();
+                            screen.tick_frame();
+                            display_filter.on_vblank(&screen);
+                            record_frame(&mut frontend, &mut recorder, &display_filter, &screen, &palette_lock.lock().unwrap());
+                        }
+
+                        if display_filter.needs_present(&screen) && scheduler.present_due() {
+                            present_screen(&mut frontend, &display_filter, &screen)?;
+                            screen.reset_changed();
                         }
                     }
 
-                    let next_frame_time = last_frame_time + cpu_time_step;
-                    if Instant::now() < next_frame_time {
+                    let cycle_due = scheduler.next_cycle();
+                    if !cycle_due && !scheduler.is_stopped() {
                         continue;
                     }
 
-                    for event in event_pump.poll_iter() {
+                    for event in frontend.poll_events() {
                         match event {
-                            Event::Quit {..} => {
+                            FrontendEvent::Quit => {
+                                export_trace(&mut frontend, &cpu_lock.lock().unwrap(), &trace_export);
+                                if let Some(active) = recorder.take() {
+                                    stop_recording(&mut frontend, active);
+                                }
                                 break 'running;
                             },
-                            _ => {},
+                            FrontendEvent::Screenshot => {
+                                take_screenshot(&mut frontend, &screen_lock.lock().unwrap(), screenshot_scale, &screenshot_directory,
+                                                &palette_lock.lock().unwrap());
+                            },
+                            FrontendEvent::ToggleRecording => {
+                                toggle_recording(&mut frontend, &mut recorder, recording_settings, &screenshot_directory);
+                            },
+                            // The screen is locked before the palette everywhere, like the screenshot routes do
+                            FrontendEvent::CyclePalette => {
+                                let screen = screen_lock.lock().unwrap();
+                                cycle_palette(&mut frontend, &mut palette_lock.lock().unwrap(), &display_filter, &screen)?;
+                            },
+                            FrontendEvent::CycleFilter => {
+                                cycle_display_filter(&mut frontend, &mut display_filter, &screen_lock.lock().unwrap())?;
+                            },
+                            FrontendEvent::Speed(control) => {
+                                scheduler.control(control);
+                                frontend.set_status(scheduler.status().as_deref());
+                            },
                         };
                     }
 
+                    if !cycle_due {
+                        continue;
+                    }
+
                     let mut paused = paused_lock.lock().unwrap();
                     let mut can_step_next = can_step_next_lock.lock().unwrap();
                     let mut cpu = cpu_lock.lock().unwrap();
@@ -260,22 +565,27 @@ impl Runtime {
 
                     let mut screen = screen_lock.lock().unwrap();
 
-                    let keyboard_state = SDL2Keyboard::new(event_pump.keyboard_state());
-                    match cpu.execute_cycle(keyboard_state, &mut screen) {
-                        Ok(_) => {},
+                    let result = cpu.execute_cycle(&*frontend.keyboard(), &mut screen);
+                    match result {
+                        Ok(Stall::Halted) => {
+                            frontend.show_message(&format!("Program halted at {:03X}", cpu.inspect_register_file().PC));
+                            *paused = IsPaused::Paused;
+                        },
+                        Ok(stall) => {
+                            scheduler.add_machine_cycles(cpu.inspect_last_machine_cycles());
+                            scheduler.stall(stall);
+                        },
+                        // The failing instruction is left at PC, so it can be inspected and stepped into again
                         Err(error) => {
-                            println!("{}", error);
-                            return Ok(());
+                            frontend.show_message(&format!("{}, pausing", error));
+                            *paused = IsPaused::Paused;
                         },
                     }
 
-                    if screen.has_changed() {
-                        draw_to_canvas(&mut canvas, &screen)?;
+                    if screen.has_changed() && display_filter.presents_immediately() && scheduler.present_due() {
+                        present_screen(&mut frontend, &display_filter, &screen)?;
                         screen.reset_changed();
-                        canvas.present();
                     }
-
-                    last_frame_time = Instant::now();
                 };
 
                 Ok(())
@@ -287,83 +597,248 @@ impl Runtime {
                         .manage(breakpoints_lock)
                         .manage(can_step_next_lock)
                         .manage(screen_lock)
-                        .mount("/", routes![add_pc_breakpoint, delete_pc_breakpoint, registers,
+                        .manage(ScreenshotSettings {
+                            scale: self.screenshot_scale,
+                            directory: self.screenshot_directory.clone(),
+                            palette: palette_lock,
+                        })
+                        .mount("/", routes![add_pc_breakpoint, delete_pc_breakpoint, registers, stack,
                                             memory, pause_emulation, resume_emulation, is_paused,
                                             step_next, last_drawn_sprite, last_draw_area,
                                             step_next_draw, last_draw_result, next_instruction,
-                                            last_instruction, instruction_trace])
+                                            last_instruction, instruction_trace, export_instruction_trace,
+                                            pixel_provenance, screen_snapshot, screenshot,
+                                            save_screenshot_route])
                         .mount("/", StaticFiles::from(concat!(env!("CARGO_MANIFEST_DIR"), "/static")))
                         .launch();
         Ok(())
     }
 
-    pub fn start(&mut self, program: Vec<u8>, cpu_clock_speed: f64) -> Result<(), Box<dyn std::error::Error>> {
-        let sdl_context = sdl2::init()?;
-        let mut event_pump = sdl_context.event_pump()?;
+    pub fn start_terminal_debug(&mut self, frontend: &mut TerminalFrontend, program: Vec<u8>,
+                                timing: Timing) -> Result<(), Box<dyn std::error::Error>> {
+        let mut cpu = CPU::new(Some(self.trace_capacity.unwrap_or(DEFAULT_TRACE_CAPACITY)));
+        cpu.set_quirks(self.quirks);
+        cpu.map_program(program)?;
+        cpu.map_digit_sprites();
+
+        let mut scheduler = Scheduler::new(timing);
+        scheduler.set_fast_forward_speed(self.fast_forward_speed);
+
+        let mut screen = Screen::new();
+        screen.enable_pixel_provenance();
+
+        let mut recorder = match &self.recording_path {
+            Some(path) => start_recording(frontend, path, self.recording_settings),
+            None => None,
+        };
+
+        let mut palette = self.palette.clone();
+        let mut display_filter = self.display_filter.clone();
+
+        let mut debugger = TerminalDebugger::new(&cpu);
+        debugger.draw_frame(frontend)?;
+        debugger.draw_panels(&cpu, frontend)?;
+
+        'running: loop {
+            for command in debugger.handle_events(&mut cpu, frontend, &mut screen)? {
+                match command {
+                    DebuggerCommand::Quit => {
+                        export_trace(frontend, &cpu, &self.trace_export);
+                        if let Some(active) = recorder.take() {
+                            stop_recording(frontend, active);
+                        }
+                        break 'running;
+                    },
+                    DebuggerCommand::Screenshot => {
+                        take_screenshot(frontend, &screen, self.screenshot_scale, &self.screenshot_directory, &palette);
+                    },
+                    DebuggerCommand::ToggleRecording => {
+                        toggle_recording(frontend, &mut recorder, self.recording_settings, &self.screenshot_directory);
+                    },
+                    DebuggerCommand::CyclePalette => {
+                        match cycle_palette(frontend, &mut palette, &display_filter, &screen) {
+                            Ok(_) => {},
+                            Err(error) => return Err(error),
+                        };
+                    },
+                    DebuggerCommand::CycleFilter => {
+                        match cycle_display_filter(frontend, &mut display_filter, &screen) {
+                            Ok(_) => {},
+                            Err(error) => return Err(error),
+                        };
+                    },
+                };
+            }
+
+            if !debugger.is_running() {
+                if screen.has_changed() {
+                    match present_screen(frontend, &display_filter, &screen) {
+                        Ok(_) => {},
+                        Err(error) => return Err(error),
+                    };
+                    screen.reset_changed();
+                }
+                thread::sleep(Duration::from_millis(10));
+                scheduler.resync();
+                continue;
+            }
+
+            let timer_ticks = scheduler.timer_ticks();
+            if timer_ticks > 0 {
+                for _ in 0..timer_ticks {
+                    cpu.tick_timers();
+                    screen.tick_frame();
+                    display_filter.on_vblank(&screen);
+                    record_frame(frontend, &mut recorder, &display_filter, &screen, &palette);
+                }
+
+                if display_filter.needs_present(&screen) && scheduler.present_due() {
+                    match present_screen(frontend, &display_filter, &screen) {
+                        Ok(_) => {},
+                        Err(error) => return Err(error),
+                    };
+                    screen.reset_changed();
+                }
+            }
 
-        let video_subsystem = sdl_context.video()?;
+            if !scheduler.next_cycle() {
+                continue;
+            }
 
-        let window = video_subsystem.window("rip8", 1280, 640).build()?;
-        let mut canvas = window.into_canvas().build()?;
+            let stall = debugger.run_cycle(&mut cpu, frontend, &mut screen);
 
-        canvas.set_draw_color(sdl2::pixels::Color::WHITE);
-        canvas.clear();
-        canvas.set_scale(20.0, 20.0)?;
-        canvas.present();
+            if screen.has_changed() && display_filter.presents_immediately() && scheduler.present_due() {
+                match present_screen(frontend, &display_filter, &screen) {
+                    Ok(_) => {},
+                    Err(error) => return Err(error),
+                };
+                screen.reset_changed();
+            }
+
+            if debugger.is_running() {
+                scheduler.add_machine_cycles(cpu.inspect_last_machine_cycles());
+                scheduler.stall(stall);
+                debugger.refresh(&cpu, frontend)?;
+            } else {
+                debugger.draw_panels(&cpu, frontend)?;
+            }
+        };
+
+        Ok(())
+    }
 
-        let mut cpu = CPU::new(false);
+    pub fn start(&mut self, frontend: &mut dyn Frontend, program: Vec<u8>, timing: Timing) -> Result<(), Box<dyn std::error::Error>> {
+        let mut cpu = CPU::new(self.trace_capacity.or_else(|| {
+            self.trace_export.as_ref().map(|_| DEFAULT_TRACE_CAPACITY)
+        }));
+        cpu.set_quirks(self.quirks);
         cpu.map_program(program)?;
         cpu.map_digit_sprites();
 
-        let cpu_time_step: Duration = Duration::new(0, (1000000000.0 / cpu_clock_speed) as u32);
-
-        let mut last_frame_time = Instant::now();
-        let mut last_timer_tick = Instant::now();
+        let mut scheduler = Scheduler::new(timing);
+        scheduler.set_fast_forward_speed(self.fast_forward_speed);
 
         let mut screen = Screen::new();
 
+        let mut recorder = match &self.recording_path {
+            Some(path) => start_recording(frontend, path, self.recording_settings),
+            None => None,
+        };
+
+        let mut palette = self.palette.clone();
+        let mut display_filter = self.display_filter.clone();
+
         'running: loop {
-            let timer_ticks = ((Instant::now() - last_timer_tick).as_secs_f64() * TIMER_HZ) as u32;
+            let timer_ticks = scheduler.timer_ticks();
             if timer_ticks > 0 {
-                last_timer_tick = Instant::now();
                 for _ in 0..timer_ticks {
                     cpu.tick_timers();
+                    screen.tick_frame();
+                    display_filter.on_vblank(&screen);
+                    record_frame(frontend, &mut recorder, &display_filter, &screen, &palette);
+                }
+
+                if display_filter.needs_present(&screen) && scheduler.present_due() {
+                    match present_screen(frontend, &display_filter, &screen) {
+                        Ok(_) => {},
+                        Err(error) => return Err(error),
+                    };
+                    screen.reset_changed();
                 }
             }
 
-            let next_frame_time = last_frame_time + cpu_time_step;
-            if Instant::now() < next_frame_time {
+            let cycle_due = scheduler.next_cycle();
+            if !cycle_due && !scheduler.is_stopped() {
                 continue;
             }
 
-            for event in event_pump.poll_iter() {
+            for event in frontend.poll_events() {
                 match event {
-                    Event::Quit {..} => {
+                    FrontendEvent::Quit => {
+                        export_trace(frontend, &cpu, &self.trace_export);
+                        if let Some(active) = recorder.take() {
+                            stop_recording(frontend, active);
+                        }
                         break 'running;
                     },
-                    _ => {},
+                    FrontendEvent::Screenshot => {
+                        take_screenshot(frontend, &screen, self.screenshot_scale, &self.screenshot_directory, &palette);
+                    },
+                    FrontendEvent::ToggleRecording => {
+                        toggle_recording(frontend, &mut recorder, self.recording_settings, &self.screenshot_directory);
+                    },
+                    FrontendEvent::CyclePalette => {
+                        match cycle_palette(frontend, &mut palette, &display_filter, &screen) {
+                            Ok(_) => {},
+                            Err(error) => return Err(error),
+                        };
+                    },
+                    FrontendEvent::CycleFilter => {
+                        match cycle_display_filter(frontend, &mut display_filter, &screen) {
+                            Ok(_) => {},
+                            Err(error) => return Err(error),
+                        };
+                    },
+                    FrontendEvent::Speed(control) => {
+                        scheduler.control(control);
+                        frontend.set_status(scheduler.status().as_deref());
+                    },
                 };
             }
 
-            let keyboard_state = SDL2Keyboard::new(event_pump.keyboard_state());
-            match cpu.execute_cycle(keyboard_state, &mut screen) {
-                Ok(_) => {},
+            if !cycle_due {
+                continue;
+            }
+
+            let result = cpu.execute_cycle(&*frontend.keyboard(), &mut screen);
+            match result {
+                Ok(stall) => {
+                    scheduler.add_machine_cycles(cpu.inspect_last_machine_cycles());
+                    scheduler.stall(stall);
+
+                    if stall == Stall::Halted {
+                        frontend.show_message(&format!("Program halted at {:03X}", cpu.inspect_register_file().PC));
+                        frontend.set_status(scheduler.status().as_deref());
+                    }
+                },
+                // The error is returned once everything is saved, so rip8 exits with a failure status
                 Err(error) => {
-                    println!("{}", error);
-                    return Ok(());
+                    write_crash_dump(frontend, &cpu, &error, &self.screenshot_directory);
+                    export_trace(frontend, &cpu, &self.trace_export);
+                    if let Some(active) = recorder.take() {
+                        stop_recording(frontend, active);
+                    }
+                    return Err(Box::new(error));
                 },
             }
 
-            if screen.has_changed() {
-                match draw_to_canvas(&mut canvas, &screen) {
+            if screen.has_changed() && display_filter.presents_immediately() && scheduler.present_due() {
+                match present_screen(frontend, &display_filter, &screen) {
                     Ok(_) => {},
                     Err(error) => return Err(error),
                 };
                 screen.reset_changed();
-                canvas.present();
             }
-
-            last_frame_time = Instant::now();
         };
 
         Ok(())
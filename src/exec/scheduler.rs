@@ -0,0 +1,555 @@
+use super::executor::Stall;
+use super::vip_timing::{ CLOCKS_PER_MACHINE_CYCLE, INTERPRETER_CYCLES_PER_FRAME, MACHINE_CYCLES_PER_FRAME, VIP_CLOCK_HZ };
+use crate::io::frontend::SpeedControl;
+
+use std::error::Error;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const TIMER_HZ: f64 = 60.0;
+pub const DEFAULT_CLOCK_SPEED: f64 = 500.0;
+
+// Speeds stepped through by the faster and slower controls
+pub const SPEED_MULTIPLIERS: [f64; 7] = [0.125, 0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Timing {
+    // Instructions run whenever a clock period has passed, timers follow wall clock time
+    ClockSpeed(f64),
+    // A fixed number of instructions per 60 Hz frame, with DT and ST ticked once at each frame boundary
+    InstructionsPerFrame(u32),
+    // Each instruction costs its COSMAC VIP machine cycles, frames last as long as the VIP's 1.76 MHz clock takes
+    CosmacVip,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Speed {
+    Multiplier(f64),
+    // Frames end as soon as their instructions have run, without waiting for the host clock
+    Uncapped,
+}
+
+#[derive(Debug)]
+pub struct InvalidSpeedError {
+    speed: String,
+}
+
+impl std::fmt::Display for InvalidSpeedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid speed {}, expected a multiplier such as 4 or 0.5, or uncapped", self.speed)
+    }
+}
+
+impl Error for InvalidSpeedError {}
+
+#[derive(Debug)]
+pub struct InvalidClockSpeedError {
+    speed: String,
+}
+
+impl std::fmt::Display for InvalidClockSpeedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid clock speed {}, expected a positive number of instructions per second", self.speed)
+    }
+}
+
+impl Error for InvalidClockSpeedError {}
+
+// The clock period is 1 / speed seconds, so both have to be finite and positive
+pub fn parse_clock_speed(speed: &str) -> Result<f64, InvalidClockSpeedError> {
+    match speed.parse::<f64>() {
+        Ok(value) if value > 0.0 && value.is_finite() && (1.0 / value).is_finite() => Ok(value),
+        _ => Err(InvalidClockSpeedError { speed: speed.to_string() }),
+    }
+}
+
+impl FromStr for Speed {
+    type Err = InvalidSpeedError;
+
+    fn from_str(s: &str) -> Result<Speed, InvalidSpeedError> {
+        if s == "uncapped" {
+            return Ok(Speed::Uncapped);
+        }
+
+        match s.trim_end_matches('x').parse::<f64>() {
+            Ok(multiplier) if multiplier > 0.0 && multiplier.is_finite() => Ok(Speed::Multiplier(multiplier)),
+            _ => Err(InvalidSpeedError { speed: s.to_string() }),
+        }
+    }
+}
+
+// Host time as seen by the scheduler, tests step it by hand instead of waiting on the wall clock
+pub trait Clock {
+    fn now(&self) -> Instant;
+    fn sleep(&self, duration: Duration);
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+pub struct Scheduler {
+    clock: Box<dyn Clock>,
+    timing: Timing,
+    cpu_time_step: Duration,
+    frame_duration: Duration,
+    speed: f64,
+    fast_forward_speed: Speed,
+    fast_forward: bool,
+    paused: bool,
+    frame_advance: bool,
+    // Emulated time runs at the current speed multiplier and stands still while paused
+    last_real_time: Instant,
+    last_present: Instant,
+    emulated_time: Duration,
+    last_cycle: Duration,
+    last_timer_tick: Duration,
+    next_frame: Duration,
+    frame_instructions: u32,
+    frame_machine_cycles: u32,
+    frame_wait: bool,
+    halted: bool,
+}
+
+impl Scheduler {
+    pub fn new(timing: Timing) -> Scheduler {
+        Scheduler::with_clock(timing, Box::new(SystemClock))
+    }
+
+    pub fn with_clock(timing: Timing, clock: Box<dyn Clock>) -> Scheduler {
+        let cpu_time_step = match timing {
+            Timing::ClockSpeed(clock_speed) => Duration::from_secs_f64(1.0 / clock_speed),
+            Timing::InstructionsPerFrame(_) | Timing::CosmacVip => Duration::from_secs(0),
+        };
+        let frame_duration = match timing {
+            Timing::CosmacVip => Duration::from_secs_f64(
+                (MACHINE_CYCLES_PER_FRAME * CLOCKS_PER_MACHINE_CYCLE) as f64 / VIP_CLOCK_HZ
+            ),
+            _ => Duration::from_secs_f64(1.0 / TIMER_HZ),
+        };
+        let now = clock.now();
+
+        Scheduler {
+            clock,
+            timing,
+            cpu_time_step,
+            frame_duration,
+            speed: 1.0,
+            fast_forward_speed: Speed::Uncapped,
+            fast_forward: false,
+            paused: false,
+            frame_advance: false,
+            last_real_time: now,
+            last_present: now,
+            emulated_time: Duration::from_secs(0),
+            last_cycle: Duration::from_secs(0),
+            last_timer_tick: Duration::from_secs(0),
+            next_frame: frame_duration,
+            frame_instructions: 0,
+            frame_machine_cycles: 0,
+            frame_wait: false,
+            halted: false,
+        }
+    }
+
+    pub fn set_fast_forward_speed(&mut self, speed: Speed) {
+        self.fast_forward_speed = speed;
+    }
+
+    fn current_speed(&self) -> Speed {
+        if self.frame_advance {
+            Speed::Uncapped
+        } else if self.paused {
+            Speed::Multiplier(0.0)
+        } else if self.fast_forward {
+            self.fast_forward_speed
+        } else {
+            Speed::Multiplier(self.speed)
+        }
+    }
+
+    fn advance_clock(&mut self) {
+        let now = self.clock.now();
+        let elapsed = now - self.last_real_time;
+        self.last_real_time = now;
+
+        if let Speed::Multiplier(multiplier) = self.current_speed() {
+            self.emulated_time += elapsed.mul_f64(multiplier);
+        }
+    }
+
+    // Whether the instructions of the current frame have all run, used when frames are not paced by the clock
+    fn frame_complete(&self) -> bool {
+        if self.frame_wait || self.halted {
+            return true;
+        }
+
+        match self.timing {
+            Timing::ClockSpeed(clock_speed) => self.frame_instructions >= ((clock_speed / TIMER_HZ) as u32).max(1),
+            Timing::InstructionsPerFrame(instructions_per_frame) => self.frame_instructions >= instructions_per_frame,
+            Timing::CosmacVip => self.frame_machine_cycles >= INTERPRETER_CYCLES_PER_FRAME,
+        }
+    }
+
+    fn start_frame(&mut self) {
+        self.frame_instructions = 0;
+        self.frame_wait = false;
+        // An instruction still running at the display interrupt finishes in the next frame
+        self.frame_machine_cycles = self.frame_machine_cycles.saturating_sub(INTERPRETER_CYCLES_PER_FRAME);
+    }
+
+    // Number of 60 Hz timer ticks that are due since the last call
+    pub fn timer_ticks(&mut self) -> u32 {
+        self.advance_clock();
+
+        if self.current_speed() == Speed::Uncapped {
+            if !self.frame_complete() {
+                return 0;
+            }
+
+            self.emulated_time += self.frame_duration;
+            self.next_frame = self.emulated_time + self.frame_duration;
+            self.last_timer_tick = self.emulated_time;
+            self.last_cycle = self.emulated_time;
+            self.frame_advance = false;
+            self.start_frame();
+            return 1;
+        }
+
+        match self.timing {
+            Timing::ClockSpeed(_) => {
+                let timer_ticks = ((self.emulated_time - self.last_timer_tick).as_secs_f64() * TIMER_HZ) as u32;
+                if timer_ticks > 0 {
+                    self.last_timer_tick = self.emulated_time;
+                    self.start_frame();
+                }
+                timer_ticks
+            },
+            Timing::InstructionsPerFrame(_) | Timing::CosmacVip => {
+                if self.emulated_time < self.next_frame {
+                    return 0;
+                }
+
+                // Frames missed while the host was busy are dropped instead of run back to back
+                self.next_frame += self.frame_duration;
+                if self.next_frame < self.emulated_time {
+                    self.next_frame = self.emulated_time + self.frame_duration;
+                }
+                self.start_frame();
+                1
+            },
+        }
+    }
+
+    // Whether the next instruction should run now, sleeping until the next frame once its instructions are used up
+    pub fn next_cycle(&mut self) -> bool {
+        self.advance_clock();
+
+        if self.halted {
+            self.clock.sleep(self.frame_duration);
+            return false;
+        }
+
+        let multiplier = match self.current_speed() {
+            Speed::Uncapped => {
+                if self.frame_complete() {
+                    return false;
+                }
+                self.frame_instructions += 1;
+                return true;
+            },
+            Speed::Multiplier(multiplier) => multiplier,
+        };
+
+        if self.paused {
+            self.clock.sleep(self.frame_duration);
+            return false;
+        }
+
+        if self.frame_wait {
+            self.sleep_until_next_frame(multiplier);
+            return false;
+        }
+
+        match self.timing {
+            Timing::ClockSpeed(_) => {
+                if self.emulated_time < self.last_cycle + self.cpu_time_step {
+                    return false;
+                }
+                self.last_cycle = self.emulated_time;
+                self.frame_instructions += 1;
+                true
+            },
+            Timing::InstructionsPerFrame(instructions_per_frame) => {
+                if self.frame_instructions < instructions_per_frame {
+                    self.frame_instructions += 1;
+                    return true;
+                }
+
+                self.sleep_until_next_frame(multiplier);
+                false
+            },
+            Timing::CosmacVip => {
+                if self.frame_machine_cycles < INTERPRETER_CYCLES_PER_FRAME {
+                    return true;
+                }
+
+                self.sleep_until_next_frame(multiplier);
+                false
+            },
+        }
+    }
+
+    // Machine cycles spent by the last instruction, only counted with COSMAC VIP timing
+    pub fn add_machine_cycles(&mut self, machine_cycles: u32) {
+        if self.timing == Timing::CosmacVip {
+            self.frame_machine_cycles += machine_cycles;
+        }
+    }
+
+    // A stalled CPU runs nothing else until the next frame boundary, when the timers tick
+    pub fn stall(&mut self, stall: Stall) {
+        match stall {
+            Stall::None => {},
+            Stall::DisplayWait | Stall::Idle => self.frame_wait = true,
+            Stall::Halted => self.halted = true,
+        };
+    }
+
+    // Sleeps at most one real frame so events are still handled in slow motion
+    fn sleep_until_next_frame(&self, multiplier: f64) {
+        let next_frame = match self.timing {
+            Timing::ClockSpeed(_) => self.last_timer_tick + self.frame_duration,
+            Timing::InstructionsPerFrame(_) | Timing::CosmacVip => self.next_frame,
+        };
+
+        if self.emulated_time < next_frame {
+            let remaining = (next_frame - self.emulated_time).div_f64(multiplier);
+            self.clock.sleep(remaining.min(self.frame_duration));
+        }
+    }
+
+    // Frames finished faster than real time are shown at most once per host frame
+    pub fn present_due(&mut self) -> bool {
+        let now = self.clock.now();
+        if self.current_speed() == Speed::Uncapped && now.saturating_duration_since(self.last_present) < self.frame_duration {
+            return false;
+        }
+
+        self.last_present = now;
+        true
+    }
+
+    // Events still have to be handled while no instructions run
+    pub fn is_stopped(&self) -> bool {
+        self.paused || self.halted
+    }
+
+    pub fn control(&mut self, control: SpeedControl) {
+        match control {
+            SpeedControl::FastForward(held) => self.fast_forward = held,
+            SpeedControl::Faster => {
+                self.speed = SPEED_MULTIPLIERS.iter().cloned().find(|speed| *speed > self.speed).unwrap_or(self.speed);
+            },
+            SpeedControl::Slower => {
+                self.speed = SPEED_MULTIPLIERS.iter().rev().cloned().find(|speed| *speed < self.speed).unwrap_or(self.speed);
+            },
+            SpeedControl::TogglePause => {
+                self.paused = !self.paused;
+                self.frame_advance = false;
+            },
+            // Advancing a frame pauses first, so the next press runs up to the next frame boundary
+            SpeedControl::AdvanceFrame => {
+                if self.paused && self.frame_complete() {
+                    self.start_frame();
+                }
+                self.frame_advance = self.paused;
+                self.paused = true;
+            },
+        };
+    }
+
+    // Nothing is shown while running at normal speed
+    pub fn status(&self) -> Option<String> {
+        match self.current_speed() {
+            _ if self.halted => Some(String::from("halted")),
+            _ if self.paused => Some(String::from("paused")),
+            Speed::Uncapped => Some(String::from("fast-forward")),
+            Speed::Multiplier(multiplier) if self.fast_forward => Some(format!("fast-forward {}x", multiplier)),
+            Speed::Multiplier(multiplier) if multiplier != 1.0 => Some(format!("{}x", multiplier)),
+            Speed::Multiplier(_) => None,
+        }
+    }
+
+    // Restarts timing from now, so time spent paused is not caught up on
+    pub fn resync(&mut self) {
+        self.last_real_time = self.clock.now();
+        self.last_cycle = self.emulated_time;
+        self.last_timer_tick = self.emulated_time;
+        self.next_frame = self.emulated_time + self.frame_duration;
+        self.frame_instructions = 0;
+        self.frame_machine_cycles = 0;
+        self.frame_wait = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ Clock, Scheduler, Timing, SPEED_MULTIPLIERS };
+    use crate::exec::executor::Stall;
+    use crate::io::frontend::SpeedControl;
+
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::time::{ Duration, Instant };
+
+    const INSTRUCTIONS_PER_FRAME: u32 = 5;
+    // Several 60 Hz frames, long enough for timers to be due when the clock runs
+    const WAIT: Duration = Duration::from_millis(60);
+
+    // Only moves when advanced or slept on
+    #[derive(Clone)]
+    struct ManualClock {
+        now: Rc<Cell<Instant>>,
+    }
+
+    impl ManualClock {
+        fn new() -> ManualClock {
+            ManualClock { now: Rc::new(Cell::new(Instant::now())) }
+        }
+
+        fn advance(&self, duration: Duration) {
+            self.now.set(self.now.get() + duration);
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> Instant {
+            self.now.get()
+        }
+
+        fn sleep(&self, duration: Duration) {
+            self.advance(duration);
+        }
+    }
+
+    fn scheduler(clock: &ManualClock) -> Scheduler {
+        Scheduler::with_clock(Timing::InstructionsPerFrame(INSTRUCTIONS_PER_FRAME), Box::new(clock.clone()))
+    }
+
+    fn paused_scheduler() -> Scheduler {
+        let mut scheduler = scheduler(&ManualClock::new());
+        scheduler.control(SpeedControl::TogglePause);
+        scheduler
+    }
+
+    // Counts the instructions let through until the scheduler holds the CPU back
+    fn run_instructions(scheduler: &mut Scheduler) -> u32 {
+        let mut instructions = 0;
+        while scheduler.next_cycle() {
+            instructions += 1;
+            assert!(instructions <= 1000, "the scheduler never stopped the CPU");
+        }
+        instructions
+    }
+
+    #[test]
+    fn advance_frame_runs_one_frame() {
+        let mut scheduler = paused_scheduler();
+        assert_eq!(run_instructions(&mut scheduler), 0);
+
+        for _ in 0..3 {
+            scheduler.control(SpeedControl::AdvanceFrame);
+            assert_eq!(run_instructions(&mut scheduler), INSTRUCTIONS_PER_FRAME);
+            assert_eq!(scheduler.timer_ticks(), 1);
+
+            // Paused again at the frame boundary
+            assert_eq!(run_instructions(&mut scheduler), 0);
+            assert_eq!(scheduler.timer_ticks(), 0);
+            assert_eq!(scheduler.status().as_deref(), Some("paused"));
+        }
+    }
+
+    #[test]
+    fn display_wait_ends_advanced_frame() {
+        let mut scheduler = paused_scheduler();
+        scheduler.control(SpeedControl::AdvanceFrame);
+
+        assert!(scheduler.next_cycle());
+        assert!(scheduler.next_cycle());
+        scheduler.stall(Stall::DisplayWait);
+        assert_eq!(run_instructions(&mut scheduler), 0);
+        assert_eq!(scheduler.timer_ticks(), 1);
+
+        // The next frame starts with its full budget
+        scheduler.control(SpeedControl::AdvanceFrame);
+        assert_eq!(run_instructions(&mut scheduler), INSTRUCTIONS_PER_FRAME);
+    }
+
+    #[test]
+    fn pause_freezes_timers() {
+        let clock = ManualClock::new();
+        let mut scheduler = scheduler(&clock);
+        scheduler.control(SpeedControl::TogglePause);
+
+        clock.advance(WAIT);
+        assert_eq!(scheduler.timer_ticks(), 0);
+        clock.advance(WAIT);
+        assert_eq!(scheduler.timer_ticks(), 0);
+
+        // Time spent paused is not caught up on, the next tick is a whole frame after resuming
+        scheduler.control(SpeedControl::TogglePause);
+        scheduler.resync();
+        clock.advance(Duration::from_millis(10));
+        assert_eq!(scheduler.timer_ticks(), 0);
+        clock.advance(Duration::from_millis(10));
+        assert_eq!(scheduler.timer_ticks(), 1);
+        assert_eq!(scheduler.timer_ticks(), 0);
+    }
+
+    #[test]
+    fn timers_tick_once_per_frame_at_normal_speed() {
+        let clock = ManualClock::new();
+        let mut scheduler = scheduler(&clock);
+
+        for _ in 0..3 {
+            assert_eq!(run_instructions(&mut scheduler), INSTRUCTIONS_PER_FRAME);
+            assert_eq!(scheduler.timer_ticks(), 1);
+        }
+
+        // Frames missed while the host was busy are dropped
+        clock.advance(WAIT);
+        assert_eq!(scheduler.timer_ticks(), 1);
+        assert_eq!(scheduler.timer_ticks(), 0);
+    }
+
+    #[test]
+    fn speed_changes_clamp_at_ends() {
+        let mut scheduler = scheduler(&ManualClock::new());
+        assert_eq!(scheduler.status(), None);
+
+        for _ in 0..SPEED_MULTIPLIERS.len() + 2 {
+            scheduler.control(SpeedControl::Faster);
+        }
+        assert_eq!(scheduler.status().as_deref(), Some("8x"));
+
+        for _ in 0..SPEED_MULTIPLIERS.len() + 2 {
+            scheduler.control(SpeedControl::Slower);
+        }
+        assert_eq!(scheduler.status().as_deref(), Some("0.125x"));
+
+        for _ in 0..3 {
+            scheduler.control(SpeedControl::Faster);
+        }
+        assert_eq!(scheduler.status(), None);
+    }
+}
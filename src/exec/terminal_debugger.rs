@@ -0,0 +1,344 @@
+use super::cpu::CPU;
+use super::executor::Stall;
+use crate::io::frontend::Frontend;
+use crate::io::screen::Screen;
+use crate::io::terminal_frontend::TerminalFrontend;
+use crate::logic::decoder;
+use crate::logic::instruction::Instruction;
+use crate::mem::register::V_REGISTERS;
+use crate::mem::RAM_SIZE;
+
+use crossterm::cursor::MoveTo;
+use crossterm::event::{poll, read, Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::style::{Attribute, Print, ResetColor, SetAttribute};
+use crossterm::terminal::{Clear, ClearType};
+use crossterm::{execute, queue};
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::io::{stdout, Stdout, Write};
+use std::time::{Duration, Instant};
+
+pub const REGISTER_PANEL_WIDTH: usize = 34;
+pub const REGISTER_PANEL_HEIGHT: u16 = 16;
+pub const DISASSEMBLY_LINES: u16 = 16;
+pub const DISASSEMBLY_WIDTH: usize = 34;
+pub const MEMORY_ROW_SIZE: usize = 8;
+pub const MEMORY_PAGE_SIZE: usize = MEMORY_ROW_SIZE * DISASSEMBLY_LINES as usize;
+pub const PANEL_REFRESH_INTERVAL: Duration = Duration::from_millis(100);
+
+const HELP: &str = "F5/c continue  Esc pause  F10/s step  F11/d step to draw  b breakpoint  \u{2191}\u{2193} cursor  p PC  PgUp/PgDn memory  i follow I  q quit";
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DebuggerState {
+    Paused,
+    Running,
+    RunningToDraw,
+}
+
+pub enum DebuggerCommand {
+    Quit,
+    Screenshot,
+    ToggleRecording,
+    CyclePalette,
+    CycleFilter,
+}
+
+pub struct TerminalDebugger {
+    state: DebuggerState,
+    breakpoints: HashSet<u16>,
+    skip_breakpoint: bool,
+    cursor: u16,
+    memory_address: usize,
+    follow_i: bool,
+    status: String,
+    last_refresh: Instant,
+    stdout: Stdout,
+}
+
+fn print_line(stdout: &mut Stdout, column: u16, row: u16, width: usize, text: &str) -> crossterm::Result<()> {
+    let text: String = text.chars().take(width).collect();
+    queue!(stdout, MoveTo(column, row), Print(format!("{:<width$}", text, width = width)))
+}
+
+pub fn disassemble(ram: &[u8], address: usize) -> String {
+    if address + 1 >= ram.len() {
+        return String::new();
+    }
+
+    let (msb, lsb) = (ram[address], ram[address + 1]);
+    match decoder::decode_instruction(msb, lsb) {
+        Ok(instruction) => format!("{:02X}{:02X}  {}", msb, lsb, instruction),
+        Err(_) => format!("{:02X}{:02X}  DW 0x{:02X}{:02X}", msb, lsb, msb, lsb),
+    }
+}
+
+impl TerminalDebugger {
+    pub fn new(cpu: &CPU) -> TerminalDebugger {
+        TerminalDebugger {
+            state: DebuggerState::Paused,
+            breakpoints: HashSet::new(),
+            skip_breakpoint: false,
+            cursor: cpu.inspect_register_file().PC,
+            memory_address: 0,
+            follow_i: true,
+            status: String::from("Paused, press F5 to run"),
+            last_refresh: Instant::now(),
+            stdout: stdout(),
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.state != DebuggerState::Paused
+    }
+
+    fn pause(&mut self, cpu: &CPU, status: String) {
+        self.state = DebuggerState::Paused;
+        self.cursor = cpu.inspect_register_file().PC;
+        self.status = status;
+    }
+
+    fn resume(&mut self, state: DebuggerState) {
+        self.state = state;
+        self.skip_breakpoint = true;
+        self.status = match state {
+            DebuggerState::RunningToDraw => String::from("Running to the next draw"),
+            _ => String::from("Running"),
+        };
+    }
+
+    fn step(&mut self, cpu: &mut CPU, frontend: &TerminalFrontend, screen: &mut Screen) {
+        let pc = cpu.inspect_register_file().PC;
+        match cpu.execute_cycle(&*frontend.keyboard(), screen) {
+            Ok(_) => self.pause(cpu, format!("Stepped {}", disassemble(cpu.inspect_memory(), pc as usize))),
+            Err(error) => self.pause(cpu, format!("Error: {}", error)),
+        }
+    }
+
+    pub fn run_cycle(&mut self, cpu: &mut CPU, frontend: &TerminalFrontend, screen: &mut Screen) -> Stall {
+        let pc = cpu.inspect_register_file().PC;
+        if !self.skip_breakpoint && self.breakpoints.contains(&pc) {
+            self.pause(cpu, format!("Hit breakpoint at {:03X}", pc));
+            return Stall::None;
+        }
+
+        if !self.skip_breakpoint && self.state == DebuggerState::RunningToDraw {
+            if let Ok(Instruction::DRW(_, _, _)) = cpu.inspect_next_instruction() {
+                self.pause(cpu, format!("Next draw at {:03X}", pc));
+                return Stall::None;
+            }
+        }
+
+        self.skip_breakpoint = false;
+
+        match cpu.execute_cycle(&*frontend.keyboard(), screen) {
+            // A halted program is paused on so it can still be inspected and stepped
+            Ok(Stall::Halted) => {
+                self.pause(cpu, format!("Program halted at {:03X}", pc));
+                Stall::None
+            },
+            Ok(stall) => stall,
+            Err(error) => {
+                self.pause(cpu, format!("Error: {}", error));
+                Stall::None
+            },
+        }
+    }
+
+    pub fn handle_events(&mut self, cpu: &mut CPU, frontend: &mut TerminalFrontend,
+                         screen: &mut Screen) -> Result<Vec<DebuggerCommand>, Box<dyn Error>> {
+        let mut commands = vec![];
+
+        while poll(Duration::from_millis(0))? {
+            let event = read()?;
+            let was_running = self.is_running();
+
+            match event {
+                Event::Key(KeyEvent { code: KeyCode::Char('c'), modifiers }) if modifiers.contains(KeyModifiers::CONTROL) => {
+                    commands.push(DebuggerCommand::Quit);
+                },
+                Event::Key(KeyEvent { code: KeyCode::Esc, .. }) => self.pause(cpu, String::from("Paused")),
+                Event::Key(KeyEvent { code: KeyCode::F(5), .. }) => {
+                    if was_running {
+                        self.pause(cpu, String::from("Paused"));
+                    } else {
+                        self.resume(DebuggerState::Running);
+                    }
+                },
+                Event::Key(KeyEvent { code: KeyCode::F(10), .. }) => self.step(cpu, frontend, screen),
+                Event::Key(KeyEvent { code: KeyCode::F(11), .. }) => self.resume(DebuggerState::RunningToDraw),
+                Event::Key(KeyEvent { code: KeyCode::F(12), .. }) => commands.push(DebuggerCommand::Screenshot),
+                Event::Key(KeyEvent { code: KeyCode::F(9), .. }) => commands.push(DebuggerCommand::ToggleRecording),
+                Event::Key(KeyEvent { code: KeyCode::F(7), .. }) => commands.push(DebuggerCommand::CyclePalette),
+                Event::Key(KeyEvent { code: KeyCode::F(6), .. }) => commands.push(DebuggerCommand::CycleFilter),
+                Event::Key(KeyEvent { code: KeyCode::Char('q'), .. }) if !was_running => commands.push(DebuggerCommand::Quit),
+                Event::Key(KeyEvent { code, .. }) if !was_running => self.handle_paused_key(code, cpu, frontend, screen),
+                Event::Resize(_, _) => {
+                    frontend.handle_event(event);
+                    self.draw_frame(frontend)?;
+                },
+                event => {
+                    frontend.handle_event(event);
+                },
+            };
+
+            if !was_running || !self.is_running() {
+                self.draw_panels(cpu, frontend)?;
+            }
+        }
+
+        Ok(commands)
+    }
+
+    fn handle_paused_key(&mut self, code: KeyCode, cpu: &mut CPU, frontend: &TerminalFrontend, screen: &mut Screen) {
+        match code {
+            KeyCode::Char('c') => self.resume(DebuggerState::Running),
+            KeyCode::Char('s') => self.step(cpu, frontend, screen),
+            KeyCode::Char('d') => self.resume(DebuggerState::RunningToDraw),
+            KeyCode::Char('b') => {
+                if self.breakpoints.remove(&self.cursor) {
+                    self.status = format!("Removed breakpoint at {:03X}", self.cursor);
+                } else {
+                    self.breakpoints.insert(self.cursor);
+                    self.status = format!("Added breakpoint at {:03X}", self.cursor);
+                }
+            },
+            KeyCode::Char('p') => self.cursor = cpu.inspect_register_file().PC,
+            KeyCode::Char('i') => {
+                self.follow_i = !self.follow_i;
+                self.memory_address = cpu.inspect_register_file().I as usize;
+            },
+            KeyCode::Up => self.cursor = self.cursor.saturating_sub(2),
+            KeyCode::Down => self.cursor = (self.cursor + 2).min(RAM_SIZE as u16 - 2),
+            KeyCode::PageUp => {
+                self.follow_i = false;
+                self.memory_address = self.memory_address.saturating_sub(MEMORY_PAGE_SIZE);
+            },
+            KeyCode::PageDown => {
+                self.follow_i = false;
+                self.memory_address = (self.memory_address + MEMORY_PAGE_SIZE).min(RAM_SIZE - MEMORY_PAGE_SIZE);
+            },
+            _ => {},
+        };
+    }
+
+    fn layout(frontend: &TerminalFrontend) -> (u16, u16) {
+        let (columns, rows) = frontend.screen_size();
+        (columns + 4, (rows + 2).max(REGISTER_PANEL_HEIGHT) + 1)
+    }
+
+    pub fn draw_frame(&mut self, frontend: &mut TerminalFrontend) -> Result<(), Box<dyn Error>> {
+        let (columns, rows) = frontend.screen_size();
+
+        execute!(self.stdout, ResetColor, Clear(ClearType::All))?;
+
+        let horizontal = "\u{2500}".repeat(columns as usize);
+        print_line(&mut self.stdout, 0, 0, columns as usize + 2, &format!("\u{250C}{}\u{2510}", horizontal))?;
+        for row in 1..(rows + 1) {
+            queue!(self.stdout, MoveTo(0, row), Print('\u{2502}'), MoveTo(columns + 1, row), Print('\u{2502}'))?;
+        }
+        print_line(&mut self.stdout, 0, rows + 1, columns as usize + 2, &format!("\u{2514}{}\u{2518}", horizontal))?;
+
+        frontend.set_origin(1, 1);
+        Ok(())
+    }
+
+    pub fn draw_panels(&mut self, cpu: &CPU, frontend: &TerminalFrontend) -> Result<(), Box<dyn Error>> {
+        let (side_column, lower_row) = TerminalDebugger::layout(frontend);
+        let registers = cpu.inspect_register_file();
+        let ram = cpu.inspect_memory();
+
+        if self.is_running() {
+            self.cursor = registers.PC;
+        }
+
+        let mut lines = vec![
+            format!("PC {:03X}  I {:03X}  SP {:02X}", registers.PC, registers.I, registers.SP),
+            format!("DT {:02X}  ST {:02X}", registers.DT, registers.ST),
+            String::new(),
+        ];
+        for row in V_REGISTERS.chunks(4) {
+            let line: Vec<String> = row
+                .iter()
+                .map(|reg| format!("{} {:02X}", reg.name(), registers.get_v_register(*reg)))
+                .collect();
+            lines.push(line.join("  "));
+        }
+
+        let stack = cpu.inspect_stack();
+        lines.push(String::new());
+        lines.push(format!("Stack ({})", stack.len()));
+        for chunk in stack.chunks(4) {
+            let addresses: Vec<String> = chunk.iter().map(|address| format!("{:03X}", address)).collect();
+            lines.push(addresses.join(" "));
+        }
+
+        let mut breakpoints: Vec<&u16> = self.breakpoints.iter().collect();
+        breakpoints.sort();
+        lines.push(String::new());
+        lines.push(format!("Breakpoints ({})", breakpoints.len()));
+        for chunk in breakpoints.chunks(6) {
+            let addresses: Vec<String> = chunk.iter().map(|address| format!("{:03X}", address)).collect();
+            lines.push(addresses.join(" "));
+        }
+
+        for row in 0..REGISTER_PANEL_HEIGHT {
+            let line = lines.get(row as usize).map(String::as_str).unwrap_or("");
+            print_line(&mut self.stdout, side_column, row, REGISTER_PANEL_WIDTH, line)?;
+        }
+
+        let first_address = self.cursor.saturating_sub(DISASSEMBLY_LINES / 2 * 2);
+        for line in 0..DISASSEMBLY_LINES {
+            let address = first_address + line * 2;
+            let row = lower_row + line;
+            let marker = if address == registers.PC { '>' } else { ' ' };
+            let breakpoint = if self.breakpoints.contains(&address) { '*' } else { ' ' };
+            let text = if (address as usize) < RAM_SIZE - 1 {
+                format!("{}{} {:03X}  {}", marker, breakpoint, address, disassemble(ram, address as usize))
+            } else {
+                String::new()
+            };
+
+            if address == self.cursor && !self.is_running() {
+                queue!(self.stdout, SetAttribute(Attribute::Reverse))?;
+                print_line(&mut self.stdout, 0, row, DISASSEMBLY_WIDTH, &text)?;
+                queue!(self.stdout, SetAttribute(Attribute::Reset))?;
+            } else {
+                print_line(&mut self.stdout, 0, row, DISASSEMBLY_WIDTH, &text)?;
+            }
+        }
+
+        let memory_address = if self.follow_i { registers.I as usize } else { self.memory_address };
+        let memory_start = (memory_address / MEMORY_ROW_SIZE * MEMORY_ROW_SIZE).min(RAM_SIZE - MEMORY_PAGE_SIZE);
+        let memory_column = DISASSEMBLY_WIDTH as u16 + 2;
+        for line in 0..DISASSEMBLY_LINES {
+            let address = memory_start + line as usize * MEMORY_ROW_SIZE;
+            let bytes: Vec<String> = ram[address..address + MEMORY_ROW_SIZE].iter().map(|byte| format!("{:02X}", byte)).collect();
+            let cursor = if self.follow_i && line == 0 { "I" } else { " " };
+            let text = format!("{}{:03X}  {}", cursor, address, bytes.join(" "));
+            print_line(&mut self.stdout, memory_column, lower_row + line, MEMORY_ROW_SIZE * 3 + 6, &text)?;
+        }
+
+        let state = match self.state {
+            DebuggerState::Paused => "PAUSED",
+            DebuggerState::Running => "RUNNING",
+            DebuggerState::RunningToDraw => "RUNNING TO DRAW",
+        };
+        let status_row = lower_row + DISASSEMBLY_LINES + 1;
+        let width = memory_column as usize + MEMORY_ROW_SIZE * 3 + 6;
+        print_line(&mut self.stdout, 0, status_row, width, &format!("[{}] {}", state, self.status))?;
+        print_line(&mut self.stdout, 0, status_row + 1, width.max(HELP.len()), HELP)?;
+
+        self.stdout.flush()?;
+        Ok(())
+    }
+
+    pub fn refresh(&mut self, cpu: &CPU, frontend: &TerminalFrontend) -> Result<(), Box<dyn Error>> {
+        if self.last_refresh.elapsed() >= PANEL_REFRESH_INTERVAL {
+            self.last_refresh = Instant::now();
+            self.draw_panels(cpu, frontend)?;
+        }
+
+        Ok(())
+    }
+}
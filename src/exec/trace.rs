@@ -0,0 +1,270 @@
+use super::executor::STACK_FRAME_SIZE;
+use crate::logic::instruction::Instruction;
+use crate::mem::register::{RegisterDelta, RegisterFile, V_REGISTERS};
+
+use serde::Serialize;
+
+use std::collections::VecDeque;
+use std::io::Write;
+use std::str::FromStr;
+
+pub const DEFAULT_TRACE_CAPACITY: usize = 4096;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TraceEntry {
+    pub cycle: u64,
+    pub pc: u16,
+    pub opcode: u16,
+    pub instruction: Instruction,
+    pub registers: RegisterFile,
+    pub deltas: Vec<RegisterDelta>,
+}
+
+impl TraceEntry {
+    pub fn to_text_line(&self) -> String {
+        let mut line = format!("PC={:04X} OP={:04X}", self.pc, self.opcode);
+        for reg in V_REGISTERS.iter() {
+            line.push_str(&format!(" {}={:02X}", reg.name(), self.registers.get_v_register(*reg)));
+        }
+        line.push_str(&format!(
+            " I={:04X} SP={:02X} DT={:02X} ST={:02X}",
+            self.registers.I,
+            self.registers.SP as usize / STACK_FRAME_SIZE,
+            self.registers.DT,
+            self.registers.ST
+        ));
+
+        line
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TraceFormat {
+    Text,
+    JsonLines,
+}
+
+#[derive(Debug)]
+pub struct InvalidTraceFormatError {
+    format: String,
+}
+
+impl std::fmt::Display for InvalidTraceFormatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Unknown trace format {}, expected text or jsonl", self.format)
+    }
+}
+
+impl std::error::Error for InvalidTraceFormatError {}
+
+impl FromStr for TraceFormat {
+    type Err = InvalidTraceFormatError;
+
+    fn from_str(s: &str) -> Result<TraceFormat, InvalidTraceFormatError> {
+        match s {
+            "text" | "txt" | "log" => Ok(TraceFormat::Text),
+            "jsonl" | "json-lines" => Ok(TraceFormat::JsonLines),
+            _ => Err(InvalidTraceFormatError { format: s.to_string() }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TraceFilter {
+    pub pc_start: Option<u16>,
+    pub pc_end: Option<u16>,
+    pub kinds: Option<Vec<String>>,
+}
+
+impl TraceFilter {
+    pub fn matches(&self, entry: &TraceEntry) -> bool {
+        if let Some(pc_start) = self.pc_start {
+            if entry.pc < pc_start {
+                return false;
+            }
+        }
+
+        if let Some(pc_end) = self.pc_end {
+            if entry.pc > pc_end {
+                return false;
+            }
+        }
+
+        match &self.kinds {
+            Some(kinds) => kinds.iter().any(|kind| kind.eq_ignore_ascii_case(entry.instruction.name())),
+            None => true,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TracePage {
+    pub total: usize,
+    pub offset: usize,
+    pub entries: Vec<TraceEntry>,
+}
+
+pub struct InstructionTrace {
+    entries: VecDeque<TraceEntry>,
+    capacity: usize,
+}
+
+impl InstructionTrace {
+    pub fn new(capacity: usize) -> InstructionTrace {
+        InstructionTrace {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn record(&mut self, entry: TraceEntry) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &TraceEntry> {
+        self.entries.iter()
+    }
+
+    pub fn filtered<'a>(&'a self, filter: &'a TraceFilter) -> impl Iterator<Item = &'a TraceEntry> {
+        self.entries.iter().filter(move |entry| filter.matches(entry))
+    }
+
+    pub fn page(&self, filter: &TraceFilter, offset: usize, limit: usize) -> TracePage {
+        let total = self.filtered(filter).count();
+        let entries = self.filtered(filter).skip(offset).take(limit).cloned().collect();
+
+        TracePage {
+            total,
+            offset,
+            entries,
+        }
+    }
+
+    pub fn export(&self, writer: &mut dyn Write, filter: &TraceFilter, format: TraceFormat) -> std::io::Result<()> {
+        for entry in self.filtered(filter) {
+            match format {
+                TraceFormat::Text => writeln!(writer, "{}", entry.to_text_line())?,
+                TraceFormat::JsonLines => writeln!(writer, "{}", serde_json::to_string(entry)?)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ InstructionTrace, TraceEntry, TraceFilter, TraceFormat };
+    use crate::logic::instruction::{ ByteOrVReg, Instruction };
+    use crate::mem::register::{ RegisterFile, VRegister };
+
+    // An LD V1, cycle instruction at PC, so every entry can be told apart by its cycle
+    fn entry(cycle: u64, pc: u16) -> TraceEntry {
+        let before = RegisterFile::new();
+        let mut registers = before;
+        registers.set_v_register(VRegister::V1, cycle as u8);
+
+        TraceEntry {
+            cycle,
+            pc,
+            opcode: 0x6100 | cycle as u16,
+            instruction: Instruction::LD(VRegister::V1, ByteOrVReg::Byte(cycle as u8)),
+            registers,
+            deltas: before.deltas(&registers),
+        }
+    }
+
+    fn cycles<'a>(entries: impl Iterator<Item = &'a TraceEntry>) -> Vec<u64> {
+        entries.map(|entry| entry.cycle).collect()
+    }
+
+    #[test]
+    fn oldest_entries_are_dropped_at_capacity() {
+        let mut trace = InstructionTrace::new(3);
+        for cycle in 1..=5 {
+            trace.record(entry(cycle, 0x200));
+        }
+        assert_eq!(cycles(trace.iter()), [3, 4, 5]);
+
+        let mut trace = InstructionTrace::new(0);
+        trace.record(entry(1, 0x200));
+        assert_eq!(trace.iter().count(), 0);
+    }
+
+    #[test]
+    fn filters_by_pc_range_and_kind() {
+        let mut trace = InstructionTrace::new(16);
+        for (cycle, pc) in [0x200, 0x202, 0x204, 0x206].iter().enumerate() {
+            trace.record(entry(cycle as u64, *pc));
+        }
+
+        // Both ends of the range are included
+        let filter = TraceFilter { pc_start: Some(0x202), pc_end: Some(0x204), kinds: None };
+        assert_eq!(cycles(trace.filtered(&filter)), [1, 2]);
+
+        let filter = TraceFilter { kinds: Some(vec![String::from("ld")]), ..TraceFilter::default() };
+        assert_eq!(cycles(trace.filtered(&filter)), [0, 1, 2, 3]);
+
+        let filter = TraceFilter { kinds: Some(vec![String::from("JP"), String::from("DRW")]), ..TraceFilter::default() };
+        assert_eq!(trace.filtered(&filter).count(), 0);
+    }
+
+    #[test]
+    fn pages_stay_within_filtered_entries() {
+        let mut trace = InstructionTrace::new(16);
+        for cycle in 0..10 {
+            trace.record(entry(cycle, 0x200 + 2 * cycle as u16));
+        }
+
+        let page = trace.page(&TraceFilter::default(), 8, 5);
+        assert_eq!((page.total, page.offset), (10, 8));
+        assert_eq!(cycles(page.entries.iter()), [8, 9]);
+
+        let page = trace.page(&TraceFilter::default(), 20, 5);
+        assert_eq!(page.total, 10);
+        assert!(page.entries.is_empty());
+
+        let filter = TraceFilter { pc_start: Some(0x20A), ..TraceFilter::default() };
+        let page = trace.page(&filter, 1, 2);
+        assert_eq!(page.total, 5);
+        assert_eq!(cycles(page.entries.iter()), [6, 7]);
+    }
+
+    #[test]
+    fn exports_one_line_per_entry() {
+        let mut trace = InstructionTrace::new(16);
+        trace.record(entry(0x12, 0x200));
+        trace.record(entry(0x34, 0x202));
+
+        let mut text = vec![];
+        trace.export(&mut text, &TraceFilter::default(), TraceFormat::Text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0],
+            "PC=0200 OP=6112 V0=00 V1=12 V2=00 V3=00 V4=00 V5=00 V6=00 V7=00 V8=00 V9=00 VA=00 VB=00 VC=00 VD=00 \
+             VE=00 VF=00 I=0000 SP=00 DT=00 ST=00"
+        );
+
+        let mut json = vec![];
+        let filter = TraceFilter { pc_start: Some(0x202), ..TraceFilter::default() };
+        trace.export(&mut json, &filter, TraceFormat::JsonLines).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert_eq!(json.lines().count(), 1);
+
+        let line: serde_json::Value = serde_json::from_str(json.trim_end()).unwrap();
+        assert_eq!(line["cycle"], 0x34);
+        assert_eq!(line["pc"], 0x202);
+        assert_eq!(line["opcode"], 0x6134);
+        assert_eq!(line["deltas"][0]["register"], "V1");
+        assert_eq!(line["deltas"][0]["new"], 0x34);
+    }
+}
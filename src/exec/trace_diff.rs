@@ -0,0 +1,257 @@
+use super::executor::STACK_FRAME_SIZE;
+use super::headless::HeadlessRunner;
+use super::quirks::Quirks;
+use super::trace::TraceEntry;
+use crate::mem::register::V_REGISTERS;
+
+use std::error::Error;
+
+#[derive(Debug)]
+pub struct ReferenceTraceParseError {
+    line: usize,
+    msg: String,
+}
+
+impl std::fmt::Display for ReferenceTraceParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid reference trace on line {}: {}", self.line, self.msg)
+    }
+}
+
+impl Error for ReferenceTraceParseError {}
+
+#[derive(Debug, Clone, Default)]
+pub struct ReferenceStep {
+    pub line: usize,
+    pub text: String,
+    pub pc: Option<u16>,
+    pub opcode: Option<u16>,
+    pub v: [Option<u8>; 16],
+    pub i: Option<u16>,
+    pub sp: Option<u8>,
+}
+
+fn parse_hex_field(field: &str, value: &str, line: usize) -> Result<u16, ReferenceTraceParseError> {
+    let value = value.trim_start_matches("0x").trim_start_matches("0X");
+    u16::from_str_radix(value, 16).map_err(|_| ReferenceTraceParseError {
+        line,
+        msg: format!("{} has non hexadecimal value {}", field, value),
+    })
+}
+
+fn parse_byte_field(field: &str, value: &str, line: usize) -> Result<u8, ReferenceTraceParseError> {
+    let parsed = parse_hex_field(field, value, line)?;
+    if parsed > 0xFF {
+        return Err(ReferenceTraceParseError {
+            line,
+            msg: format!("{} value {} does not fit in a byte", field, value),
+        });
+    }
+
+    Ok(parsed as u8)
+}
+
+pub fn parse_reference_step(text: &str, line: usize) -> Result<ReferenceStep, ReferenceTraceParseError> {
+    let mut step = ReferenceStep {
+        line,
+        text: text.trim().to_string(),
+        ..ReferenceStep::default()
+    };
+
+    for token in text.split(|c: char| c.is_whitespace() || c == ',') {
+        let mut parts = token.splitn(2, |c| c == '=' || c == ':');
+        let (field, value) = match (parts.next(), parts.next()) {
+            (Some(field), Some(value)) if !value.is_empty() => (field.to_ascii_uppercase(), value),
+            _ => continue,
+        };
+
+        match field.as_str() {
+            "PC" => step.pc = Some(parse_hex_field(&field, value, line)?),
+            "OP" | "OPCODE" => step.opcode = Some(parse_hex_field(&field, value, line)?),
+            "I" => step.i = Some(parse_hex_field(&field, value, line)?),
+            "SP" => step.sp = Some(parse_byte_field(&field, value, line)?),
+            _ => {
+                if field.len() == 2 && field.starts_with('V') {
+                    if let Ok(index) = usize::from_str_radix(&field[1..], 16) {
+                        step.v[index] = Some(parse_byte_field(&field, value, line)?);
+                    }
+                }
+            }
+        }
+    }
+
+    if step.pc.is_none() {
+        return Err(ReferenceTraceParseError {
+            line,
+            msg: "missing PC field".to_string(),
+        });
+    }
+
+    Ok(step)
+}
+
+pub fn parse_reference_trace(trace: &str) -> Result<Vec<ReferenceStep>, ReferenceTraceParseError> {
+    let mut steps = vec![];
+    for (i, text) in trace.lines().enumerate() {
+        let trimmed = text.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        steps.push(parse_reference_step(trimmed, i + 1)?);
+    }
+
+    Ok(steps)
+}
+
+fn compare_field(differences: &mut Vec<String>, field: &str, expected: Option<u16>, actual: u16, width: usize) {
+    if let Some(expected) = expected {
+        if expected != actual {
+            differences.push(format!(
+                "{} expected {:0width$X}, got {:0width$X}",
+                field,
+                expected,
+                actual,
+                width = width
+            ));
+        }
+    }
+}
+
+pub fn compare_step(reference: &ReferenceStep, entry: &TraceEntry) -> Vec<String> {
+    let mut differences = vec![];
+
+    compare_field(&mut differences, "PC", reference.pc, entry.pc, 4);
+    compare_field(&mut differences, "OP", reference.opcode, entry.opcode, 4);
+    for (index, reg) in V_REGISTERS.iter().enumerate() {
+        let actual = entry.registers.get_v_register(*reg) as u16;
+        compare_field(&mut differences, reg.name(), reference.v[index].map(|v| v as u16), actual, 2);
+    }
+    compare_field(&mut differences, "I", reference.i, entry.registers.I, 4);
+    let stack_depth = (entry.registers.SP as usize / STACK_FRAME_SIZE) as u16;
+    compare_field(&mut differences, "SP", reference.sp.map(|sp| sp as u16), stack_depth, 2);
+
+    differences
+}
+
+pub enum TraceDiffResult {
+    Matched(usize),
+    Diverged(String),
+}
+
+pub fn run_trace_diff(program: Vec<u8>, reference: &[ReferenceStep], instructions_per_frame: u32,
+                      history: usize, quirks: Quirks) -> Result<TraceDiffResult, Box<dyn Error>> {
+    let mut runner = HeadlessRunner::new(program, instructions_per_frame, Some(history + 1), quirks)?;
+
+    for (step, expected) in reference.iter().enumerate() {
+        if let Err(error) = runner.step() {
+            let mut report = format!("Emulation failed at step {} (reference line {}): {}\n", step, expected.line, error);
+            report.push_str(&format!("  expected: {}\n", expected.text));
+            report.push_str(&format_history(&runner, history));
+            return Ok(TraceDiffResult::Diverged(report));
+        }
+
+        let trace = runner.cpu().inspect_instruction_trace().unwrap();
+        let entry = trace.iter().next_back().unwrap();
+        let differences = compare_step(expected, entry);
+
+        if !differences.is_empty() {
+            let mut report = format!("Divergence at step {} (reference line {})\n", step, expected.line);
+            report.push_str(&format!("  expected: {}\n", expected.text));
+            report.push_str(&format!("  actual:   {}\n", entry.to_text_line()));
+            for difference in differences {
+                report.push_str(&format!("  {}\n", difference));
+            }
+            report.push_str(&format_history(&runner, history));
+            return Ok(TraceDiffResult::Diverged(report));
+        }
+    }
+
+    Ok(TraceDiffResult::Matched(reference.len()))
+}
+
+fn format_history(runner: &HeadlessRunner, history: usize) -> String {
+    let mut report = String::from("History (oldest first):\n");
+    if let Some(trace) = runner.cpu().inspect_instruction_trace() {
+        let entries: Vec<&TraceEntry> = trace.iter().rev().take(history).collect();
+        for entry in entries.into_iter().rev() {
+            report.push_str(&format!("  {:>8} {} {:?}\n", entry.cycle, entry.to_text_line(), entry.instruction));
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ compare_step, parse_reference_step, parse_reference_trace };
+    use crate::exec::trace::TraceEntry;
+    use crate::logic::instruction::{ ByteOrVReg, Instruction };
+    use crate::mem::register::{ RegisterFile, VRegister };
+
+    fn entry(pc: u16, opcode: u16, registers: RegisterFile) -> TraceEntry {
+        TraceEntry {
+            cycle: 0,
+            pc,
+            opcode,
+            instruction: Instruction::LD(VRegister::V3, ByteOrVReg::Byte(0x42)),
+            registers,
+            deltas: vec![],
+        }
+    }
+
+    #[test]
+    fn parses_fields_in_either_notation() {
+        let step = parse_reference_step("PC=0200 OP=6342 V3=42 VF=01 I=0ABC SP=02", 7).unwrap();
+        assert_eq!(step.line, 7);
+        assert_eq!(step.pc, Some(0x200));
+        assert_eq!(step.opcode, Some(0x6342));
+        assert_eq!(step.v[0x3], Some(0x42));
+        assert_eq!(step.v[0xF], Some(0x01));
+        assert_eq!(step.v[0x0], None);
+        assert_eq!(step.i, Some(0xABC));
+        assert_eq!(step.sp, Some(0x02));
+
+        let step = parse_reference_step("pc:0x0202, opcode:0x00E0, va:ff", 1).unwrap();
+        assert_eq!(step.pc, Some(0x202));
+        assert_eq!(step.opcode, Some(0x00E0));
+        assert_eq!(step.v[0xA], Some(0xFF));
+
+        // Tokens that are not fields, such as a disassembly, are skipped
+        let step = parse_reference_step("12 PC=0204 LD V3, 42 DT=00", 1).unwrap();
+        assert_eq!(step.pc, Some(0x204));
+        assert_eq!(step.opcode, None);
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        assert!(parse_reference_step("OP=6342 V3=42", 3).is_err());
+        assert!(parse_reference_step("PC=02G0", 3).is_err());
+        assert!(parse_reference_step("PC=0200 V3=100", 3).is_err());
+        assert!(parse_reference_step("PC=0200 SP=1FF", 3).is_err());
+        assert!(parse_reference_step("PC=10000", 3).is_err());
+
+        let error = parse_reference_trace("# comment\n\nPC=0200\nPC=0202 V1=zz\n").unwrap_err();
+        assert_eq!(error.line, 4);
+    }
+
+    #[test]
+    fn compares_only_fields_in_the_reference() {
+        let mut registers = RegisterFile::new();
+        registers.set_v_register(VRegister::V3, 0x42);
+        registers.I = 0x300;
+        registers.SP = 4;
+        let actual = entry(0x200, 0x6342, registers);
+
+        let step = parse_reference_step("PC=0200 OP=6342 V3=42 I=0300 SP=02", 1).unwrap();
+        assert!(compare_step(&step, &actual).is_empty());
+        assert!(compare_step(&parse_reference_step("PC=0200", 1).unwrap(), &actual).is_empty());
+
+        let step = parse_reference_step("PC=0202 OP=6342 V3=43 V4=00 I=0300 SP=01", 1).unwrap();
+        assert_eq!(compare_step(&step, &actual), [
+            "PC expected 0202, got 0200",
+            "V3 expected 43, got 42",
+            "SP expected 01, got 02",
+        ]);
+    }
+}
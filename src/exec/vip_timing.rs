@@ -0,0 +1,78 @@
+use crate::logic::instruction::{ ByteOrVReg, Instruction };
+use crate::mem::register::{ RegisterFile, VRegister };
+
+// The VIP's 1802 runs at 1.76 MHz and takes 8 clock cycles per machine cycle
+pub const VIP_CLOCK_HZ: f64 = 1_760_640.0;
+pub const CLOCKS_PER_MACHINE_CYCLE: u32 = 8;
+pub const MACHINE_CYCLES_PER_FRAME: u32 = 3668;
+
+// Each frame the 1861 fetches 128 lines of 8 bytes by DMA, and the interrupt routine counts down DT and ST
+pub const DISPLAY_DMA_CYCLES: u32 = 128 * 8;
+pub const INTERRUPT_ROUTINE_CYCLES: u32 = 30;
+pub const DISPLAY_INTERRUPT_CYCLES: u32 = DISPLAY_DMA_CYCLES + INTERRUPT_ROUTINE_CYCLES;
+
+// Machine cycles left for the interpreter in each frame
+pub const INTERPRETER_CYCLES_PER_FRAME: u32 = MACHINE_CYCLES_PER_FRAME - DISPLAY_INTERRUPT_CYCLES;
+
+// Fetching the opcode and jumping to its routine through the interpreter's dispatch table
+pub const FETCH_DECODE_CYCLES: u32 = 40;
+pub const SKIP_CYCLES: u32 = 4;
+
+// Sprites are drawn a byte at a time, shifted right one bit per loop to the column inside the display byte
+pub const DRW_SETUP_CYCLES: u32 = 68;
+pub const DRW_ROW_CYCLES: u32 = 30;
+pub const DRW_SHIFT_CYCLES: u32 = 8;
+
+// CLS stores zero over the 256 display bytes with a four instruction loop
+pub const CLS_CYCLES: u32 = 24 + 256 * 8;
+
+fn register_index(register: VRegister) -> u32 {
+    register as u32
+}
+
+// Machine cycles the VIP interpreter spends on an instruction, given the registers before it ran
+pub fn machine_cycles(instruction: Instruction, registers: &RegisterFile, skipped: bool) -> u32 {
+    let skip = if skipped { SKIP_CYCLES } else { 0 };
+
+    let execution = match instruction {
+        Instruction::CLS => CLS_CYCLES,
+        Instruction::RET => 10,
+        Instruction::JP(_) => 12,
+        Instruction::CALL(_) => 26,
+        Instruction::SE(_, ByteOrVReg::Byte(_)) | Instruction::SNE(_, ByteOrVReg::Byte(_)) => 10 + skip,
+        Instruction::SE(_, ByteOrVReg::Register(_)) | Instruction::SNE(_, ByteOrVReg::Register(_)) => 14 + skip,
+        Instruction::LD(_, ByteOrVReg::Byte(_)) => 6,
+        Instruction::ADD(_, ByteOrVReg::Byte(_)) => 10,
+        // 8XYN instructions are run by writing the matching 1802 instruction into RAM and calling it
+        Instruction::LD(_, ByteOrVReg::Register(_)) |
+        Instruction::ADD(_, ByteOrVReg::Register(_)) |
+        Instruction::OR(_, _) |
+        Instruction::AND(_, _) |
+        Instruction::XOR(_, _) |
+        Instruction::SUB(_, _) |
+        Instruction::SHR(_) |
+        Instruction::SUBN(_, _) |
+        Instruction::SHL(_) => 44,
+        Instruction::ADDI(_) => 16,
+        Instruction::LDI(_) => 12,
+        Instruction::JPV0(_) => 22,
+        Instruction::RND(_, _) => 36,
+        Instruction::DRW(x, _, rows) => {
+            let shifts = (registers.get_v_register(x) % 8) as u32;
+            DRW_SETUP_CYCLES + rows as u32 * (DRW_ROW_CYCLES + shifts * DRW_SHIFT_CYCLES)
+        },
+        Instruction::SKP(_) | Instruction::SKNP(_) => 14 + skip,
+        Instruction::LDK(_) => 36,
+        Instruction::LDF(_) => 20,
+        Instruction::LD_TO_DT(_) | Instruction::LD_FROM_DT(_) | Instruction::LDST(_) => 10,
+        // Each digit is found by repeated subtraction of its power of ten
+        Instruction::LDBCD(x) => {
+            let value = registers.get_v_register(x) as u32;
+            let digits = value / 100 + (value / 10) % 10 + value % 10;
+            84 + digits * 16
+        },
+        Instruction::LDARR(x) | Instruction::RDARR(x) => 14 + (register_index(x) + 1) * 14,
+    };
+
+    FETCH_DECODE_CYCLES + execution
+}
@@ -0,0 +1,315 @@
+use super::image::Rgb;
+use super::palette::Palette;
+use super::screen::{Screen, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::str::FromStr;
+
+pub const DEFAULT_PHOSPHOR_DECAY: f32 = 0.6;
+pub const DEFAULT_BLEND_FRAMES: usize = 3;
+pub const FRAME_SIZE: usize = SCREEN_WIDTH * SCREEN_HEIGHT;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DisplayFilterKind {
+    None,
+    Phosphor,
+    Blend,
+    Vblank,
+}
+
+pub const DISPLAY_FILTERS: [DisplayFilterKind; 4] = [
+    DisplayFilterKind::None,
+    DisplayFilterKind::Phosphor,
+    DisplayFilterKind::Blend,
+    DisplayFilterKind::Vblank,
+];
+
+#[derive(Debug)]
+pub struct InvalidDisplayFilterError {
+    filter: String,
+}
+
+impl std::fmt::Display for InvalidDisplayFilterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Unknown display filter {}, expected none, phosphor, blend or vblank", self.filter)
+    }
+}
+
+impl Error for InvalidDisplayFilterError {}
+
+impl FromStr for DisplayFilterKind {
+    type Err = InvalidDisplayFilterError;
+
+    fn from_str(s: &str) -> Result<DisplayFilterKind, InvalidDisplayFilterError> {
+        match s {
+            "none" => Ok(DisplayFilterKind::None),
+            "phosphor" => Ok(DisplayFilterKind::Phosphor),
+            "blend" => Ok(DisplayFilterKind::Blend),
+            "vblank" => Ok(DisplayFilterKind::Vblank),
+            _ => Err(InvalidDisplayFilterError { filter: s.to_string() }),
+        }
+    }
+}
+
+// Pixel intensities between 0.0 (off) and 1.0 (on) and the planes each pixel was last lit on, in row-major order
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    intensities: Vec<f32>,
+    planes: Vec<u8>,
+}
+
+impl Frame {
+    pub fn from_screen(screen: &Screen) -> Frame {
+        let mut intensities = Vec::with_capacity(FRAME_SIZE);
+        let mut planes = Vec::with_capacity(FRAME_SIZE);
+        for row in screen.inspect_screen().iter() {
+            intensities.extend(row.iter().map(|pixel| if *pixel { 1.0 } else { 0.0 }));
+            planes.extend(row.iter().map(|pixel| *pixel as u8));
+        }
+
+        Frame { intensities, planes }
+    }
+
+    pub fn intensity(&self, x: usize, y: usize) -> f32 {
+        self.intensities[y * SCREEN_WIDTH + x]
+    }
+
+    pub fn planes(&self, x: usize, y: usize) -> usize {
+        self.planes[y * SCREEN_WIDTH + x] as usize
+    }
+
+    pub fn color(&self, x: usize, y: usize, palette: &Palette) -> Rgb {
+        palette.mix(self.planes(x, y), self.intensity(x, y))
+    }
+
+    pub fn is_lit(&self, x: usize, y: usize) -> bool {
+        self.intensity(x, y) >= 0.5
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DisplayFilter {
+    kind: DisplayFilterKind,
+    phosphor_decay: f32,
+    blend_frames: usize,
+    phosphor: Vec<f32>,
+    phosphor_planes: Vec<u8>,
+    history: VecDeque<Frame>,
+}
+
+impl DisplayFilter {
+    pub fn new(kind: DisplayFilterKind) -> DisplayFilter {
+        DisplayFilter {
+            kind,
+            phosphor_decay: DEFAULT_PHOSPHOR_DECAY,
+            blend_frames: DEFAULT_BLEND_FRAMES,
+            phosphor: vec![0.0; FRAME_SIZE],
+            phosphor_planes: vec![0; FRAME_SIZE],
+            history: VecDeque::new(),
+        }
+    }
+
+    pub fn set_kind(&mut self, kind: DisplayFilterKind) {
+        self.kind = kind;
+        self.phosphor = vec![0.0; FRAME_SIZE];
+        self.phosphor_planes = vec![0; FRAME_SIZE];
+        self.history.clear();
+    }
+
+    pub fn cycle(&mut self) {
+        let index = DISPLAY_FILTERS.iter().position(|kind| *kind == self.kind).unwrap_or(0);
+        self.set_kind(DISPLAY_FILTERS[(index + 1) % DISPLAY_FILTERS.len()]);
+    }
+
+    pub fn set_phosphor_decay(&mut self, decay: f32) {
+        self.phosphor_decay = decay.max(0.0).min(1.0);
+    }
+
+    pub fn set_blend_frames(&mut self, frames: usize) {
+        self.blend_frames = frames.max(1);
+    }
+
+    // Without a filter every change is shown as soon as it happens, like the original draw_to_canvas did
+    pub fn presents_immediately(&self) -> bool {
+        self.kind == DisplayFilterKind::None
+    }
+
+    // Filtered frames keep changing after the screen stops, so they are presented every vblank
+    pub fn needs_present(&self, screen: &Screen) -> bool {
+        match self.kind {
+            DisplayFilterKind::None | DisplayFilterKind::Vblank => screen.has_changed(),
+            DisplayFilterKind::Phosphor | DisplayFilterKind::Blend => true,
+        }
+    }
+
+    pub fn on_vblank(&mut self, screen: &Screen) {
+        match self.kind {
+            DisplayFilterKind::None | DisplayFilterKind::Vblank => {},
+            DisplayFilterKind::Phosphor => {
+                let frame = Frame::from_screen(screen);
+                for (index, lit) in frame.intensities.iter().enumerate() {
+                    self.phosphor[index] = lit.max(self.phosphor[index] * self.phosphor_decay);
+                    // Fading pixels keep the color of the planes they were lit on
+                    if frame.planes[index] != 0 {
+                        self.phosphor_planes[index] = frame.planes[index];
+                    }
+                }
+            },
+            DisplayFilterKind::Blend => {
+                self.history.push_back(Frame::from_screen(screen));
+                while self.history.len() > self.blend_frames {
+                    self.history.pop_front();
+                }
+            },
+        };
+    }
+
+    pub fn frame(&self, screen: &Screen) -> Frame {
+        match self.kind {
+            DisplayFilterKind::None | DisplayFilterKind::Vblank => Frame::from_screen(screen),
+            DisplayFilterKind::Phosphor => Frame {
+                intensities: self.phosphor.clone(),
+                planes: self.phosphor_planes.clone(),
+            },
+            DisplayFilterKind::Blend => {
+                if self.history.is_empty() {
+                    return Frame::from_screen(screen);
+                }
+
+                let mut intensities = vec![0.0; FRAME_SIZE];
+                let mut planes = vec![0; FRAME_SIZE];
+                for frame in self.history.iter() {
+                    for (sum, intensity) in intensities.iter_mut().zip(frame.intensities.iter()) {
+                        *sum += intensity;
+                    }
+                    for (latest, lit) in planes.iter_mut().zip(frame.planes.iter()) {
+                        if *lit != 0 {
+                            *latest = *lit;
+                        }
+                    }
+                }
+
+                let frames = self.history.len() as f32;
+                Frame {
+                    intensities: intensities.into_iter().map(|sum| sum / frames).collect(),
+                    planes,
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DisplayFilter, DisplayFilterKind};
+    use crate::io::palette::Palette;
+    use crate::io::screen::Screen;
+
+    // Lights the top left pixel
+    fn lit_screen() -> Screen {
+        let mut screen = Screen::new();
+        screen.draw(0, 0, vec![0x80], 0x200, 0x300).unwrap();
+        screen
+    }
+
+    fn phosphor(decay: f32) -> DisplayFilter {
+        let mut filter = DisplayFilter::new(DisplayFilterKind::Phosphor);
+        filter.set_phosphor_decay(decay);
+        filter
+    }
+
+    #[test]
+    fn phosphor_decays_unlit_pixels() {
+        let (lit, dark) = (lit_screen(), Screen::new());
+        let mut filter = phosphor(0.5);
+
+        filter.on_vblank(&lit);
+        assert_eq!(filter.frame(&lit).intensity(0, 0), 1.0);
+        filter.on_vblank(&dark);
+        assert_eq!(filter.frame(&dark).intensity(0, 0), 0.5);
+        filter.on_vblank(&dark);
+        assert_eq!(filter.frame(&dark).intensity(0, 0), 0.25);
+        assert_eq!(filter.frame(&dark).intensity(1, 0), 0.0);
+    }
+
+    #[test]
+    fn phosphor_decay_of_zero_turns_pixels_off_at_once() {
+        let (lit, dark) = (lit_screen(), Screen::new());
+        let mut filter = phosphor(0.0);
+
+        filter.on_vblank(&lit);
+        filter.on_vblank(&dark);
+        assert_eq!(filter.frame(&dark).intensity(0, 0), 0.0);
+    }
+
+    #[test]
+    fn phosphor_decay_of_one_keeps_pixels_lit() {
+        let (lit, dark) = (lit_screen(), Screen::new());
+        let mut filter = phosphor(1.0);
+
+        filter.on_vblank(&lit);
+        for _ in 0..10 {
+            filter.on_vblank(&dark);
+        }
+        assert_eq!(filter.frame(&dark).intensity(0, 0), 1.0);
+        assert_eq!(phosphor(2.0).phosphor_decay, 1.0);
+        assert_eq!(phosphor(-1.0).phosphor_decay, 0.0);
+    }
+
+    #[test]
+    fn fading_pixels_keep_their_plane_color() {
+        let (lit, dark) = (lit_screen(), Screen::new());
+        let palette: Palette = "000000,FF0000".parse().unwrap();
+        let mut filter = phosphor(0.5);
+
+        filter.on_vblank(&lit);
+        filter.on_vblank(&dark);
+        let frame = filter.frame(&dark);
+        assert_eq!(frame.planes(0, 0), 1);
+        assert_eq!(frame.color(0, 0, &palette), [128, 0, 0]);
+        assert_eq!(frame.color(1, 0, &palette), [0, 0, 0]);
+    }
+
+    #[test]
+    fn blend_averages_the_last_frames() {
+        let (lit, dark) = (lit_screen(), Screen::new());
+        let mut filter = DisplayFilter::new(DisplayFilterKind::Blend);
+        filter.set_blend_frames(2);
+
+        assert_eq!(filter.frame(&lit).intensity(0, 0), 1.0);
+        filter.on_vblank(&lit);
+        filter.on_vblank(&dark);
+        let frame = filter.frame(&dark);
+        assert_eq!(frame.intensity(0, 0), 0.5);
+        assert_eq!(frame.planes(0, 0), 1);
+        filter.on_vblank(&dark);
+        assert_eq!(filter.frame(&dark).intensity(0, 0), 0.0);
+    }
+
+    #[test]
+    fn blend_of_one_frame_shows_the_last_vblank() {
+        let (lit, dark) = (lit_screen(), Screen::new());
+        let mut filter = DisplayFilter::new(DisplayFilterKind::Blend);
+        filter.set_blend_frames(0);
+        assert_eq!(filter.blend_frames, 1);
+
+        filter.on_vblank(&lit);
+        assert_eq!(filter.frame(&dark).intensity(0, 0), 1.0);
+        filter.on_vblank(&dark);
+        assert_eq!(filter.frame(&lit).intensity(0, 0), 0.0);
+    }
+
+    #[test]
+    fn cycling_resets_the_filter_state() {
+        let lit = lit_screen();
+        let mut filter = phosphor(1.0);
+        filter.on_vblank(&lit);
+
+        for _ in 0..4 {
+            filter.cycle();
+        }
+        assert_eq!(filter.kind, DisplayFilterKind::Phosphor);
+        assert_eq!(filter.frame(&lit).intensity(0, 0), 0.0);
+    }
+}
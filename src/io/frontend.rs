@@ -0,0 +1,40 @@
+use super::display_filter::Frame;
+use super::keys::Keyboard;
+use super::palette::Palette;
+
+use std::error::Error;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FrontendEvent {
+    Quit,
+    Screenshot,
+    ToggleRecording,
+    CyclePalette,
+    CycleFilter,
+    Speed(SpeedControl),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SpeedControl {
+    // Held down to fast-forward, released to return to the normal speed
+    FastForward(bool),
+    Faster,
+    Slower,
+    TogglePause,
+    AdvanceFrame,
+}
+
+pub trait Frontend {
+    fn poll_events(&mut self) -> Vec<FrontendEvent>;
+    fn keyboard(&self) -> Box<dyn Keyboard + '_>;
+    fn present(&mut self, frame: &Frame) -> Result<(), Box<dyn Error + Send + Sync>>;
+    fn set_palette(&mut self, palette: &Palette);
+
+    // Shows the emulation speed, e.g. 2x or paused, where the frontend has room for it
+    fn set_status(&mut self, _status: Option<&str>) {}
+
+    // Reports screenshots, recordings and errors, frontends without a status line print them
+    fn show_message(&mut self, message: &str) {
+        println!("{}", message);
+    }
+}
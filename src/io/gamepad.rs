@@ -0,0 +1,62 @@
+use super::keys::{Key, Keyboard};
+
+use sdl2::controller::{Axis, Button, GameController};
+
+pub const GAMEPAD_PREFIX: &str = "pad:";
+pub const AXIS_THRESHOLD: i16 = 16384;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum GamepadInput {
+    Button(Button),
+    AxisPositive(Axis),
+    AxisNegative(Axis),
+}
+
+impl GamepadInput {
+    pub fn from_name(name: &str) -> Option<GamepadInput> {
+        let input = name.strip_prefix(GAMEPAD_PREFIX)?;
+
+        if let Some(axis) = input.strip_suffix('+') {
+            Axis::from_string(axis).map(GamepadInput::AxisPositive)
+        } else if let Some(axis) = input.strip_suffix('-') {
+            Axis::from_string(axis).map(GamepadInput::AxisNegative)
+        } else {
+            Button::from_string(input).map(GamepadInput::Button)
+        }
+    }
+
+    pub fn is_held(&self, controller: &GameController) -> bool {
+        match self {
+            GamepadInput::Button(button) => controller.button(*button),
+            GamepadInput::AxisPositive(axis) => controller.axis(*axis) > AXIS_THRESHOLD,
+            GamepadInput::AxisNegative(axis) => controller.axis(*axis) < -AXIS_THRESHOLD,
+        }
+    }
+}
+
+pub fn is_gamepad_name(name: &str) -> bool {
+    name.starts_with(GAMEPAD_PREFIX)
+}
+
+pub struct GamepadKeyboard<'a> {
+    controllers: &'a [GameController],
+    bindings: &'a [(GamepadInput, Key)],
+}
+
+impl Keyboard for GamepadKeyboard<'_> {
+    fn is_key_pressed(&self, key: Key) -> bool {
+        self.bindings
+            .iter()
+            .filter(|(_, bound)| *bound == key)
+            .any(|(input, _)| self.controllers.iter().any(|controller| input.is_held(controller)))
+    }
+}
+
+impl GamepadKeyboard<'_> {
+    pub fn new<'a>(controllers: &'a [GameController], bindings: &'a [(GamepadInput, Key)]) -> GamepadKeyboard<'a> {
+        GamepadKeyboard {
+            controllers,
+            bindings,
+        }
+    }
+}
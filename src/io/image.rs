@@ -0,0 +1,204 @@
+use super::display_filter::Frame;
+use super::palette::Palette;
+use super::screen::{Screen, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub type Rgb = [u8; 3];
+
+pub const MONOCHROME_ON: Rgb = [255, 255, 255];
+pub const MONOCHROME_OFF: Rgb = [0, 0, 0];
+// Larger screenshots are of no use and their size quickly overflows
+pub const MAX_SCREENSHOT_SCALE: u32 = 64;
+
+#[derive(Debug)]
+pub struct UnsupportedImageError {
+    msg: String,
+}
+
+impl std::fmt::Display for UnsupportedImageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.msg)
+    }
+}
+
+impl Error for UnsupportedImageError {}
+
+#[derive(Debug)]
+pub struct InvalidColorError {
+    color: String,
+}
+
+impl std::fmt::Display for InvalidColorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid color {}, expected a hex color such as #FFA424", self.color)
+    }
+}
+
+impl Error for InvalidColorError {}
+
+#[derive(Debug)]
+pub struct InvalidScaleError {
+    scale: String,
+    max: u32,
+}
+
+impl std::fmt::Display for InvalidScaleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid scale {}, expected 1 to {}", self.scale, self.max)
+    }
+}
+
+impl Error for InvalidScaleError {}
+
+pub fn parse_scale(scale: &str, max: u32) -> Result<u32, InvalidScaleError> {
+    match scale.parse() {
+        Ok(value) if (1..=max).contains(&value) => Ok(value),
+        _ => Err(InvalidScaleError { scale: scale.to_string(), max }),
+    }
+}
+
+pub fn parse_hex_color(color: &str) -> Result<Rgb, InvalidColorError> {
+    let error = || InvalidColorError { color: color.to_string() };
+
+    let hex = color.trim().trim_start_matches('#');
+    if hex.len() != 6 {
+        return Err(error());
+    }
+
+    let value = u32::from_str_radix(hex, 16).map_err(|_| error())?;
+    Ok([(value >> 16) as u8, (value >> 8) as u8, value as u8])
+}
+
+#[derive(Debug, Clone)]
+pub struct RgbImage {
+    pub width: u32,
+    pub height: u32,
+    data: Vec<u8>,
+}
+
+impl RgbImage {
+    pub fn new(width: u32, height: u32) -> RgbImage {
+        let size = (width as usize).checked_mul(height as usize).and_then(|pixels| pixels.checked_mul(3));
+
+        RgbImage {
+            width,
+            height,
+            data: vec![0; size.expect("Image size overflows usize")],
+        }
+    }
+
+    fn offset(&self, x: u32, y: u32) -> usize {
+        (y as usize * self.width as usize + x as usize) * 3
+    }
+
+    pub fn get_pixel(&self, x: u32, y: u32) -> Rgb {
+        let offset = self.offset(x, y);
+        [self.data[offset], self.data[offset + 1], self.data[offset + 2]]
+    }
+
+    pub fn set_pixel(&mut self, x: u32, y: u32, color: Rgb) {
+        let offset = self.offset(x, y);
+        self.data[offset..offset + 3].copy_from_slice(&color);
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn fill_cell(&mut self, column: usize, row: usize, scale: u32, color: Rgb) {
+        for y in 0..scale {
+            for x in 0..scale {
+                self.set_pixel(column as u32 * scale + x, row as u32 * scale + y, color);
+            }
+        }
+    }
+
+    pub fn write_png(&self, writer: impl Write) -> Result<(), Box<dyn Error>> {
+        let mut encoder = png::Encoder::new(writer, self.width, self.height);
+        encoder.set_color(png::ColorType::RGB);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut png_writer = encoder.write_header()?;
+        png_writer.write_image_data(&self.data)?;
+
+        Ok(())
+    }
+
+    pub fn save_png(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        self.write_png(BufWriter::new(File::create(path)?))
+    }
+
+    pub fn load_png(path: &Path) -> Result<RgbImage, Box<dyn Error>> {
+        let mut decoder = png::Decoder::new(File::open(path)?);
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+
+        let (info, mut reader) = decoder.read_info()?;
+        let mut buffer = vec![0; info.buffer_size()];
+        reader.next_frame(&mut buffer)?;
+
+        let samples = info.color_type.samples();
+        if info.bit_depth != png::BitDepth::Eight {
+            return Err(Box::new(UnsupportedImageError {
+                msg: format!("Unsupported bit depth {:?} in {}", info.bit_depth, path.display()),
+            }));
+        }
+
+        let mut image = RgbImage::new(info.width, info.height);
+        for (i, pixel) in buffer.chunks(samples).enumerate() {
+            let color = match samples {
+                1 | 2 => [pixel[0], pixel[0], pixel[0]],
+                _ => [pixel[0], pixel[1], pixel[2]],
+            };
+            image.set_pixel(i as u32 % info.width, i as u32 / info.width, color);
+        }
+
+        Ok(image)
+    }
+}
+
+pub fn render_screen(screen: &Screen, scale: u32, on_color: Rgb, off_color: Rgb) -> RgbImage {
+    let scale = scale.max(1);
+    let mut image = RgbImage::new(SCREEN_WIDTH as u32 * scale, SCREEN_HEIGHT as u32 * scale);
+
+    for (row, row_arr) in screen.inspect_screen().iter().enumerate() {
+        for (column, pixel) in row_arr.iter().enumerate() {
+            let color = if *pixel { on_color } else { off_color };
+            image.fill_cell(column, row, scale, color);
+        }
+    }
+
+    image
+}
+
+pub fn render_frame(frame: &Frame, scale: u32, palette: &Palette) -> RgbImage {
+    let scale = scale.max(1);
+    let mut image = RgbImage::new(SCREEN_WIDTH as u32 * scale, SCREEN_HEIGHT as u32 * scale);
+
+    for row in 0..SCREEN_HEIGHT {
+        for column in 0..SCREEN_WIDTH {
+            image.fill_cell(column, row, scale, frame.color(column, row, palette));
+        }
+    }
+
+    image
+}
+
+pub fn save_screenshot(screen: &Screen, directory: &Path, scale: u32, on_color: Rgb, off_color: Rgb) -> Result<PathBuf, Box<dyn Error>> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+    let path = directory.join(format!("rip8-{}.png", timestamp));
+
+    render_screen(screen, scale, on_color, off_color).save_png(&path)?;
+
+    let snapshot = screen.snapshot();
+    if snapshot.provenance.is_some() {
+        let provenance_file = File::create(path.with_extension("provenance.json"))?;
+        serde_json::to_writer(BufWriter::new(provenance_file), &snapshot)?;
+    }
+
+    Ok(path)
+}
@@ -0,0 +1,374 @@
+use super::gamepad::{is_gamepad_name, GamepadInput};
+use super::keys::Key;
+
+use sdl2::keyboard::{Keycode, Scancode};
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const KEYPAD_ORDER: [Key; 16] = [0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF];
+pub const DEFAULT_LAYOUT: &str = "qwerty";
+pub const KEYMAP_EXTENSION: &str = "keymap";
+
+// Keys for 123C 456D 789E A0BF in keypad order, named by the label printed on the key
+pub const LAYOUTS: [(&str, &str); 5] = [
+    ("qwerty", "1234qwerasdfzxcv"),
+    ("azerty", "1234azerqsdfwxcv"),
+    ("qwertz", "1234qwerasdfyxcv"),
+    ("dvorak", "1234',.paoeu;qjk"),
+    ("colemak", "1234qwfparstzxcv"),
+];
+
+// Directions on the D-pad and left stick follow the 5/7/8/9 movement keys most ROMs use
+pub const DEFAULT_GAMEPAD_BINDINGS: [(Key, &str); 10] = [
+    (0x5, "pad:dpup"),
+    (0x5, "pad:lefty-"),
+    (0x8, "pad:dpdown"),
+    (0x8, "pad:lefty+"),
+    (0x7, "pad:dpleft"),
+    (0x7, "pad:leftx-"),
+    (0x9, "pad:dpright"),
+    (0x9, "pad:leftx+"),
+    (0x6, "pad:a"),
+    (0x4, "pad:b"),
+];
+
+#[derive(Debug)]
+pub struct InvalidKeymapError {
+    msg: String,
+}
+
+impl std::fmt::Display for InvalidKeymapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.msg)
+    }
+}
+
+impl Error for InvalidKeymapError {}
+
+#[derive(Debug, Clone)]
+pub struct KeyMap {
+    bindings: HashMap<Key, Vec<String>>,
+    // Character keys read by the terminal frontend instead of those in bindings, set by --terminal-keymap
+    terminal_bindings: Option<HashMap<Key, Vec<char>>>,
+}
+
+fn char_name(c: char) -> String {
+    match c {
+        ' ' => String::from("Space"),
+        ',' => String::from("Comma"),
+        c => c.to_ascii_uppercase().to_string(),
+    }
+}
+
+fn name_char(name: &str) -> Option<char> {
+    match name {
+        "Space" => Some(' '),
+        "Comma" => Some(','),
+        name if is_gamepad_name(name) => None,
+        _ => {
+            let mut chars = name.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Some(c.to_ascii_lowercase()),
+                _ => None,
+            }
+        },
+    }
+}
+
+fn name_scancode(name: &str) -> Option<Scancode> {
+    let sdl_name = match name {
+        "Comma" => ",",
+        name => name,
+    };
+
+    Keycode::from_name(sdl_name)
+        .and_then(Scancode::from_keycode)
+        .or_else(|| Scancode::from_name(sdl_name))
+}
+
+impl KeyMap {
+    fn keypad_chars(keys: &str) -> Result<Vec<(Key, char)>, InvalidKeymapError> {
+        let chars: Vec<char> = keys.chars().collect();
+        if chars.len() != KEYPAD_ORDER.len() {
+            return Err(InvalidKeymapError {
+                msg: format!("Keymap {} must list 16 keys in keypad order (123C 456D 789E A0BF)", keys),
+            });
+        }
+
+        Ok(KEYPAD_ORDER.iter().cloned().zip(chars.into_iter()).collect())
+    }
+
+    pub fn from_keys(keys: &str) -> Result<KeyMap, InvalidKeymapError> {
+        let bindings = KeyMap::keypad_chars(keys)?
+            .into_iter()
+            .map(|(key, c)| (key, vec![char_name(c)]))
+            .collect();
+
+        Ok(KeyMap {
+            bindings,
+            terminal_bindings: None,
+        })
+    }
+
+    // Replaces the keys the terminal frontend reads, keeping the SDL keys and gamepad inputs
+    pub fn set_terminal_keys(&mut self, keys: &str) -> Result<(), InvalidKeymapError> {
+        let terminal_bindings = KeyMap::keypad_chars(keys)?
+            .into_iter()
+            .map(|(key, c)| (key, vec![c.to_ascii_lowercase()]))
+            .collect();
+
+        self.terminal_bindings = Some(terminal_bindings);
+        Ok(())
+    }
+
+    pub fn layout(name: &str) -> Result<KeyMap, InvalidKeymapError> {
+        match LAYOUTS.iter().find(|(layout, _)| *layout == name.to_ascii_lowercase()) {
+            Some((_, keys)) => {
+                let mut key_map = KeyMap::from_keys(keys)?;
+                for (key, name) in DEFAULT_GAMEPAD_BINDINGS.iter() {
+                    key_map.bindings.entry(*key).or_insert_with(Vec::new).push(name.to_string());
+                }
+                Ok(key_map)
+            },
+            None => {
+                let names: Vec<&str> = LAYOUTS.iter().map(|(layout, _)| *layout).collect();
+                Err(InvalidKeymapError {
+                    msg: format!("Unknown keymap layout {}, expected a keymap file or one of {}", name, names.join(", ")),
+                })
+            },
+        }
+    }
+
+    pub fn load(layout_or_path: &str) -> Result<KeyMap, Box<dyn Error>> {
+        let path = Path::new(layout_or_path);
+        if !path.is_file() {
+            return Ok(KeyMap::layout(layout_or_path)?);
+        }
+
+        let mut key_map = KeyMap::layout(DEFAULT_LAYOUT)?;
+        key_map.apply_file(path)?;
+        Ok(key_map)
+    }
+
+    pub fn apply_file(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let contents = fs::read_to_string(path)?;
+        match self.apply(&contents) {
+            Ok(_) => Ok(()),
+            Err(error) => Err(Box::new(InvalidKeymapError {
+                msg: format!("{}: {}", path.display(), error),
+            })),
+        }
+    }
+
+    pub fn apply(&mut self, contents: &str) -> Result<(), InvalidKeymapError> {
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let error = |msg: &str| InvalidKeymapError {
+                msg: format!("line {}: {}", number + 1, msg),
+            };
+
+            let mut parts = line.splitn(2, '=');
+            let target = parts.next().unwrap_or("").trim();
+            let names = match parts.next() {
+                Some(names) => names,
+                None => return Err(error("expected KEY = NAME[, NAME...]")),
+            };
+
+            if target.eq_ignore_ascii_case("layout") {
+                *self = KeyMap::layout(names.trim()).map_err(|layout_error| error(&layout_error.msg))?;
+                continue;
+            }
+
+            let key = match u8::from_str_radix(target, 16) {
+                Ok(key) if key <= 0xF => key,
+                _ => return Err(error(&format!("{} is not a CHIP-8 key, expected 0-F", target))),
+            };
+
+            let names: Vec<String> = names
+                .split(',')
+                .map(|name| name.trim())
+                .filter(|name| !name.is_empty())
+                .map(|name| match name_char(name) {
+                    Some(c) => char_name(c),
+                    None if is_gamepad_name(&name.to_ascii_lowercase()) => name.to_ascii_lowercase(),
+                    None => name.to_string(),
+                })
+                .collect();
+
+            for bound in self.bindings.values_mut() {
+                bound.retain(|name| !names.contains(name));
+            }
+
+            // Character keys bound later, e.g. by a per-ROM file, take over in the terminal as well
+            let chars: Vec<char> = names.iter().filter_map(|name| name_char(name)).collect();
+            if let Some(terminal_bindings) = self.terminal_bindings.as_mut().filter(|_| !chars.is_empty()) {
+                for bound in terminal_bindings.values_mut() {
+                    bound.retain(|c| !chars.contains(c));
+                }
+                terminal_bindings.insert(key, chars);
+            }
+
+            self.bindings.insert(key, names);
+        }
+
+        Ok(())
+    }
+
+    pub fn bindings(&self, key: Key) -> &[String] {
+        match self.bindings.get(&key) {
+            Some(names) => names,
+            None => &[],
+        }
+    }
+
+    pub fn unbound_keys(&self) -> Vec<Key> {
+        KEYPAD_ORDER.iter().cloned().filter(|key| self.bindings(*key).is_empty()).collect()
+    }
+
+    pub fn chars(&self) -> HashMap<char, Key> {
+        let mut chars = HashMap::new();
+        if let Some(terminal_bindings) = &self.terminal_bindings {
+            for (key, bound) in terminal_bindings.iter() {
+                for c in bound.iter() {
+                    chars.insert(*c, *key);
+                }
+            }
+            return chars;
+        }
+
+        for (key, names) in self.bindings.iter() {
+            for c in names.iter().filter_map(|name| name_char(name)) {
+                chars.insert(c, *key);
+            }
+        }
+
+        chars
+    }
+
+    pub fn scancodes(&self) -> HashMap<Key, Vec<Scancode>> {
+        let mut scancodes = HashMap::new();
+        for (key, names) in self.bindings.iter() {
+            let mut resolved = vec![];
+            for name in names.iter().filter(|name| !is_gamepad_name(name)) {
+                if let Some(scancode) = name_scancode(name) {
+                    resolved.push(scancode);
+                }
+            }
+            scancodes.insert(*key, resolved);
+        }
+
+        scancodes
+    }
+
+    pub fn gamepad_bindings(&self) -> Vec<(GamepadInput, Key)> {
+        let mut bindings = vec![];
+        for (key, names) in self.bindings.iter() {
+            for name in names.iter().filter(|name| is_gamepad_name(name)) {
+                if let Some(input) = GamepadInput::from_name(name) {
+                    bindings.push((input, *key));
+                }
+            }
+        }
+
+        bindings
+    }
+
+    // Reported through the frontend, since stdout belongs to the terminal frontend while it runs
+    pub fn warnings(&self) -> Vec<String> {
+        let mut keys: Vec<&Key> = self.bindings.keys().collect();
+        keys.sort();
+
+        let mut warnings = vec![];
+        for key in keys {
+            for name in self.bindings(*key) {
+                if is_gamepad_name(name) && GamepadInput::from_name(name).is_none() {
+                    warnings.push(format!("Ignoring unknown gamepad input {} mapped to CHIP-8 key {:X}", name, key));
+                } else if !is_gamepad_name(name) && name_scancode(name).is_none() {
+                    warnings.push(format!("Ignoring unknown key {} mapped to CHIP-8 key {:X}", name, key));
+                }
+            }
+        }
+
+        warnings
+    }
+}
+
+pub fn rom_keymap_path(rom_path: &Path) -> Option<PathBuf> {
+    let path = rom_path.with_extension(KEYMAP_EXTENSION);
+    if path.is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{KeyMap, DEFAULT_LAYOUT};
+
+    #[test]
+    fn from_keys_binds_keys_in_keypad_order() {
+        let key_map = KeyMap::from_keys("1234qwerasdfzxcv").unwrap();
+
+        assert_eq!(key_map.bindings(0x1), ["1"]);
+        assert_eq!(key_map.bindings(0xC), ["4"]);
+        assert_eq!(key_map.bindings(0x0), ["X"]);
+        assert_eq!(key_map.bindings(0xF), ["V"]);
+        assert_eq!(key_map.chars().get(&'x'), Some(&0x0));
+        assert!(KeyMap::from_keys("1234qwer").is_err());
+    }
+
+    #[test]
+    fn apply_moves_keys_between_chip8_keys() {
+        let mut key_map = KeyMap::layout(DEFAULT_LAYOUT).unwrap();
+        key_map.apply("# comment\n\n5 = Z, Up\n").unwrap();
+
+        assert_eq!(key_map.bindings(0x5), ["Z", "Up"]);
+        assert!(key_map.bindings(0xA).is_empty());
+        assert_eq!(key_map.unbound_keys(), vec![0xA]);
+        assert_eq!(key_map.chars().get(&'z'), Some(&0x5));
+    }
+
+    #[test]
+    fn apply_keeps_unknown_key_names() {
+        let mut key_map = KeyMap::layout(DEFAULT_LAYOUT).unwrap();
+        key_map.apply("5 = NoSuchKey").unwrap();
+
+        assert_eq!(key_map.bindings(0x5), ["NoSuchKey"]);
+        assert!(!key_map.chars().values().any(|key| *key == 0x5));
+    }
+
+    #[test]
+    fn apply_rejects_malformed_lines() {
+        let mut key_map = KeyMap::layout(DEFAULT_LAYOUT).unwrap();
+
+        assert!(key_map.apply("5 Z").is_err());
+        assert!(key_map.apply("G = Z").is_err());
+        assert!(key_map.apply("layout = nope").is_err());
+    }
+
+    #[test]
+    fn terminal_keys_leave_other_bindings_alone() {
+        let mut key_map = KeyMap::layout(DEFAULT_LAYOUT).unwrap();
+        key_map.apply("5 = W, Up").unwrap();
+        key_map.set_terminal_keys("1234uiopjkl;m,./").unwrap();
+
+        assert_eq!(key_map.bindings(0x5), ["W", "Up"]);
+        assert!(key_map.bindings(0x8).contains(&String::from("pad:dpdown")));
+        assert_eq!(key_map.chars().get(&'i'), Some(&0x5));
+        assert_eq!(key_map.chars().get(&'w'), None);
+
+        // A per-ROM file applied afterwards still rebinds the terminal
+        key_map.apply("5 = T").unwrap();
+        assert_eq!(key_map.chars().get(&'t'), Some(&0x5));
+        assert_eq!(key_map.chars().get(&'i'), None);
+        assert_eq!(key_map.chars().get(&'k'), Some(&0x8));
+    }
+}
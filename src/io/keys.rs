@@ -4,58 +4,127 @@ use std::collections::HashMap;
 
 pub type Key = u8;
 
+pub type KeyStates = [bool; 16];
+
 pub trait Keyboard {
     fn is_key_pressed(&self, key: Key) -> bool;
+
+    fn was_key_pressed(&self, _key: Key) -> bool {
+        false
+    }
+
+    fn was_key_released(&self, _key: Key) -> bool {
+        false
+    }
 }
 
 impl<'a> Keyboard for &'a dyn Keyboard {
     fn is_key_pressed(&self, key: Key) -> bool {
         (*self).is_key_pressed(key)
     }
+
+    fn was_key_pressed(&self, key: Key) -> bool {
+        (*self).was_key_pressed(key)
+    }
+
+    fn was_key_released(&self, key: Key) -> bool {
+        (*self).was_key_released(key)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct KeyTransitions {
+    previous: KeyStates,
+    current: KeyStates,
+}
+
+impl Keyboard for KeyTransitions {
+    fn is_key_pressed(&self, key: Key) -> bool {
+        self.current.get(key as usize).cloned().unwrap_or(false)
+    }
+
+    fn was_key_pressed(&self, key: Key) -> bool {
+        self.is_key_pressed(key) && !self.previous.get(key as usize).cloned().unwrap_or(false)
+    }
+
+    fn was_key_released(&self, key: Key) -> bool {
+        !self.is_key_pressed(key) && self.previous.get(key as usize).cloned().unwrap_or(false)
+    }
+}
+
+impl KeyTransitions {
+    pub fn new(keyboard: &dyn Keyboard, previous: KeyStates) -> KeyTransitions {
+        let mut current = [false; 16];
+        for (key, state) in current.iter_mut().enumerate() {
+            *state = keyboard.is_key_pressed(key as Key);
+        }
+
+        KeyTransitions {
+            previous,
+            current,
+        }
+    }
+
+    pub fn key_states(&self) -> KeyStates {
+        self.current
+    }
+}
+
+pub struct CombinedKeyboard<A: Keyboard, B: Keyboard>(pub A, pub B);
+
+impl<A: Keyboard, B: Keyboard> Keyboard for CombinedKeyboard<A, B> {
+    fn is_key_pressed(&self, key: Key) -> bool {
+        self.0.is_key_pressed(key) || self.1.is_key_pressed(key)
+    }
 }
 
 pub struct SDL2Keyboard<'a> {
     keyboard_state: KeyboardState<'a>,
-    key_map: HashMap<Key, Scancode>,
+    key_map: &'a HashMap<Key, Vec<Scancode>>,
 }
 
 impl Keyboard for SDL2Keyboard<'_> {
     fn is_key_pressed(&self, key: Key) -> bool {
-        let scancode = self
-            .key_map
-            .get(&key)
-            .expect(format!("Unmapped key {}", key).as_str());
-
-        self.keyboard_state.is_scancode_pressed(*scancode)
+        match self.key_map.get(&key) {
+            Some(scancodes) => scancodes.iter().any(|scancode| self.keyboard_state.is_scancode_pressed(*scancode)),
+            None => false,
+        }
     }
 }
 
 impl SDL2Keyboard<'_> {
-    pub fn new<'a>(keyboard_state: KeyboardState<'a>) -> SDL2Keyboard<'a> {
-        let key_map: HashMap<Key, Scancode> = [
-            (0x1, Scancode::Num1),
-            (0x2, Scancode::Num2),
-            (0x3, Scancode::Num3),
-            (0xC, Scancode::Num4),
-            (0x4, Scancode::Q),
-            (0x5, Scancode::W),
-            (0x6, Scancode::E),
-            (0xD, Scancode::R),
-            (0x7, Scancode::A),
-            (0x8, Scancode::S),
-            (0x9, Scancode::D),
-            (0xE, Scancode::F),
-            (0xA, Scancode::Z),
-            (0x0, Scancode::X),
-            (0xB, Scancode::C),
-            (0xF, Scancode::V),
-        ]
-        .iter()
-        .cloned()
-        .collect();
+    pub fn new<'a>(keyboard_state: KeyboardState<'a>, key_map: &'a HashMap<Key, Vec<Scancode>>) -> SDL2Keyboard<'a> {
         SDL2Keyboard {
-            keyboard_state: keyboard_state,
-            key_map: key_map,
+            keyboard_state,
+            key_map,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct HeadlessKeyboard {
+    pressed: [bool; 16],
+}
+
+impl Keyboard for HeadlessKeyboard {
+    fn is_key_pressed(&self, key: Key) -> bool {
+        match self.pressed.get(key as usize) {
+            Some(pressed) => *pressed,
+            None => false,
+        }
+    }
+}
+
+impl HeadlessKeyboard {
+    pub fn new() -> HeadlessKeyboard {
+        HeadlessKeyboard {
+            pressed: [false; 16],
+        }
+    }
+
+    pub fn set_key_pressed(&mut self, key: Key, pressed: bool) {
+        if let Some(state) = self.pressed.get_mut(key as usize) {
+            *state = pressed;
         }
     }
 }
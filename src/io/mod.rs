@@ -1,2 +1,11 @@
+pub mod display_filter;
+pub mod frontend;
+pub mod gamepad;
+pub mod image;
+pub mod keymap;
 pub mod keys;
+pub mod palette;
+pub mod recording;
 pub mod screen;
+pub mod sdl_frontend;
+pub mod terminal_frontend;
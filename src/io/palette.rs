@@ -0,0 +1,274 @@
+use super::image::{parse_hex_color, Rgb};
+
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+pub const DEFAULT_PALETTE: &str = "rip8";
+
+// Colors are indexed by the set of planes a pixel is lit on: background, plane 1, plane 2, both planes
+pub const PALETTE_SIZE: usize = 4;
+
+pub const PALETTES: [(&str, [Rgb; PALETTE_SIZE]); 6] = [
+    ("rip8", [[255, 164, 36], [186, 109, 0], [255, 214, 140], [110, 60, 0]]),
+    ("classic-green", [[12, 24, 12], [51, 255, 102], [20, 140, 60], [190, 255, 200]]),
+    ("amber", [[20, 12, 0], [255, 176, 0], [150, 90, 0], [255, 226, 150]]),
+    ("lcd", [[155, 188, 15], [15, 56, 15], [48, 98, 48], [139, 172, 15]]),
+    ("high-contrast", [[0, 0, 0], [255, 255, 255], [255, 255, 0], [0, 255, 255]]),
+    ("monochrome", [[0, 0, 0], [255, 255, 255], [170, 170, 170], [85, 85, 85]]),
+];
+
+#[derive(Debug)]
+pub struct InvalidPaletteError {
+    msg: String,
+}
+
+impl std::fmt::Display for InvalidPaletteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.msg)
+    }
+}
+
+impl Error for InvalidPaletteError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    pub name: String,
+    colors: [Rgb; PALETTE_SIZE],
+}
+
+impl Palette {
+    pub fn named(name: &str) -> Option<Palette> {
+        PALETTES
+            .iter()
+            .find(|(palette, _)| *palette == name.to_ascii_lowercase())
+            .map(|(palette, colors)| Palette {
+                name: palette.to_string(),
+                colors: *colors,
+            })
+    }
+
+    pub fn background(&self) -> Rgb {
+        self.colors[0]
+    }
+
+    pub fn foreground(&self) -> Rgb {
+        self.colors[1]
+    }
+
+    pub fn plane_color(&self, planes: usize) -> Rgb {
+        self.colors[planes.min(PALETTE_SIZE - 1)]
+    }
+
+    // Fades from the background to the color of the planes a pixel was lit on, for filtered frames
+    pub fn mix(&self, planes: usize, intensity: f32) -> Rgb {
+        let intensity = intensity.max(0.0).min(1.0);
+        let (background, foreground) = (self.background(), self.plane_color(planes));
+
+        let mut color = [0; 3];
+        for (channel, value) in color.iter_mut().enumerate() {
+            let from = background[channel] as f32;
+            let to = foreground[channel] as f32;
+            *value = (from + (to - from) * intensity).round() as u8;
+        }
+
+        color
+    }
+
+    pub fn set_color(&mut self, index: usize, color: Rgb) {
+        if let Some(slot) = self.colors.get_mut(index) {
+            *slot = color;
+            self.name = String::from("custom");
+        }
+    }
+
+    pub fn next(&self) -> Palette {
+        let index = PALETTES.iter().position(|(name, _)| *name == self.name);
+        let (name, colors) = match index {
+            Some(index) => PALETTES[(index + 1) % PALETTES.len()],
+            None => PALETTES[0],
+        };
+
+        Palette {
+            name: name.to_string(),
+            colors,
+        }
+    }
+
+    pub fn load(name_or_path: &str) -> Result<Palette, Box<dyn Error>> {
+        let path = Path::new(name_or_path);
+        if path.is_file() {
+            Palette::from_file(path)
+        } else {
+            Ok(name_or_path.parse()?)
+        }
+    }
+
+    pub fn from_file(path: &Path) -> Result<Palette, Box<dyn Error>> {
+        let contents = fs::read_to_string(path)?;
+        Ok(Palette::parse_file(path, &contents)?)
+    }
+
+    fn parse_file(path: &Path, contents: &str) -> Result<Palette, InvalidPaletteError> {
+        let mut palette = Palette::default();
+
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let error = |msg: String| InvalidPaletteError {
+                msg: format!("{}: line {}: {}", path.display(), number + 1, msg),
+            };
+
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap_or("").trim();
+            let value = match parts.next() {
+                Some(value) => value.trim(),
+                None => return Err(error(String::from("expected NAME = VALUE"))),
+            };
+
+            let index = match key {
+                "base" => {
+                    palette = value.parse().map_err(|parse_error: InvalidPaletteError| error(parse_error.msg))?;
+                    continue;
+                },
+                "background" => 0,
+                "foreground" => 1,
+                "plane2" => 2,
+                "blend" => 3,
+                _ => return Err(error(format!("unknown color {}, expected base, background, foreground, plane2 or blend", key))),
+            };
+
+            let color = parse_hex_color(value).map_err(|color_error| error(color_error.to_string()))?;
+            palette.set_color(index, color);
+        }
+
+        Ok(palette)
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette::named(DEFAULT_PALETTE).unwrap()
+    }
+}
+
+impl FromStr for Palette {
+    type Err = InvalidPaletteError;
+
+    // A palette name or hex colors in index order, starting with the background
+    fn from_str(s: &str) -> Result<Palette, InvalidPaletteError> {
+        if let Some(palette) = Palette::named(s) {
+            return Ok(palette);
+        }
+
+        if !s.contains(',') {
+            let names: Vec<&str> = PALETTES.iter().map(|(name, _)| *name).collect();
+            return Err(InvalidPaletteError {
+                msg: format!("Unknown palette {}, expected one of {} or hex colors such as 000000,FFFFFF", s, names.join(", ")),
+            });
+        }
+
+        let mut palette = Palette::default();
+        let colors: Vec<&str> = s.split(',').collect();
+        if colors.len() > PALETTE_SIZE {
+            return Err(InvalidPaletteError {
+                msg: format!("Palette {} has more than {} colors", s, PALETTE_SIZE),
+            });
+        }
+
+        for (index, color) in colors.into_iter().enumerate() {
+            let color = parse_hex_color(color).map_err(|error| InvalidPaletteError { msg: error.to_string() })?;
+            palette.set_color(index, color);
+        }
+
+        Ok(palette)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Palette, PALETTES};
+
+    use std::path::Path;
+
+    #[test]
+    fn parses_names_case_insensitively() {
+        let palette: Palette = "Amber".parse().unwrap();
+
+        assert_eq!(palette.name, "amber");
+        assert_eq!(palette.background(), [20, 12, 0]);
+        assert!("no-such-palette".parse::<Palette>().is_err());
+    }
+
+    #[test]
+    fn parses_hex_colors_over_the_default_palette() {
+        let palette: Palette = "000000,#FFFFFF".parse().unwrap();
+
+        assert_eq!(palette.name, "custom");
+        assert_eq!(palette.background(), [0, 0, 0]);
+        assert_eq!(palette.foreground(), [255, 255, 255]);
+        assert_eq!(palette.plane_color(2), Palette::default().plane_color(2));
+    }
+
+    #[test]
+    fn rejects_malformed_hex_colors() {
+        assert!("000000,FFFFFF,000000,FFFFFF,000000".parse::<Palette>().is_err());
+        assert!("000000,FFFFF".parse::<Palette>().is_err());
+        assert!("000000,GGGGGG".parse::<Palette>().is_err());
+        assert!("000000,".parse::<Palette>().is_err());
+    }
+
+    #[test]
+    fn mix_clamps_intensity() {
+        let palette: Palette = "000000,FF8000".parse().unwrap();
+
+        assert_eq!(palette.mix(1, 0.0), [0, 0, 0]);
+        assert_eq!(palette.mix(1, 0.5), [128, 64, 0]);
+        assert_eq!(palette.mix(1, 1.0), [255, 128, 0]);
+        assert_eq!(palette.mix(1, -1.0), [0, 0, 0]);
+        assert_eq!(palette.mix(1, 2.0), [255, 128, 0]);
+    }
+
+    #[test]
+    fn mix_fades_to_the_plane_color() {
+        let palette: Palette = "000000,FFFFFF,FF0000,00FF00".parse().unwrap();
+
+        assert_eq!(palette.mix(2, 1.0), [255, 0, 0]);
+        assert_eq!(palette.mix(3, 0.5), [0, 128, 0]);
+        assert_eq!(palette.mix(7, 1.0), [0, 255, 0]);
+        assert_eq!(palette.mix(0, 1.0), [0, 0, 0]);
+    }
+
+    #[test]
+    fn next_cycles_through_built_in_palettes() {
+        let last = Palette::named(PALETTES[PALETTES.len() - 1].0).unwrap();
+
+        assert_eq!(last.next(), Palette::named(PALETTES[0].0).unwrap());
+        assert_eq!("000000,FFFFFF".parse::<Palette>().unwrap().next().name, PALETTES[0].0);
+    }
+
+    #[test]
+    fn palette_files_override_a_base_palette() {
+        let contents = "# Comment\n\nbase = lcd\nforeground = 102030\nblend = #A0B0C0\n";
+        let palette = Palette::parse_file(Path::new("test.palette"), contents).unwrap();
+
+        assert_eq!(palette.background(), Palette::named("lcd").unwrap().background());
+        assert_eq!(palette.foreground(), [0x10, 0x20, 0x30]);
+        assert_eq!(palette.plane_color(3), [0xA0, 0xB0, 0xC0]);
+    }
+
+    #[test]
+    fn palette_files_with_errors_report_the_line() {
+        let path = Path::new("test.palette");
+        let error = |contents: &str| Palette::parse_file(path, contents).unwrap_err().to_string();
+
+        assert!(error("base = 000000,111111,222222,333333,444444").starts_with("test.palette: line 1: "));
+        assert!(error("background = 000000\nplane3 = FFFFFF").starts_with("test.palette: line 2: unknown color plane3"));
+        assert!(error("foreground FFFFFF").contains("expected NAME = VALUE"));
+        assert!(error("foreground = FFFFFFFF").starts_with("test.palette: line 1: "));
+    }
+}
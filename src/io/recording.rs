@@ -0,0 +1,225 @@
+use super::display_filter::Frame;
+use super::image::{render_frame, Rgb, MAX_SCREENSHOT_SCALE};
+use super::palette::Palette;
+use super::screen::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+pub const RECORDING_FPS: u32 = 60;
+pub const DEFAULT_RECORDING_SCALE: u32 = 4;
+// Every frame is rendered at this scale, so it is bounded like screenshots are
+pub const MAX_RECORDING_SCALE: u32 = MAX_SCREENSHOT_SCALE;
+// Filtered intensities are rounded to this many steps so a GIF frame fits in a 256 color palette
+const GIF_INTENSITY_LEVELS: f32 = 63.0;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RecordingFormat {
+    Gif,
+    Raw,
+    Y4m,
+}
+
+#[derive(Debug)]
+pub struct InvalidRecordingFormatError {
+    format: String,
+}
+
+impl std::fmt::Display for InvalidRecordingFormatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Unknown recording format {}, expected gif, raw or y4m", self.format)
+    }
+}
+
+impl Error for InvalidRecordingFormatError {}
+
+impl FromStr for RecordingFormat {
+    type Err = InvalidRecordingFormatError;
+
+    fn from_str(s: &str) -> Result<RecordingFormat, InvalidRecordingFormatError> {
+        match s {
+            "gif" => Ok(RecordingFormat::Gif),
+            "raw" | "rgb" => Ok(RecordingFormat::Raw),
+            "y4m" => Ok(RecordingFormat::Y4m),
+            _ => Err(InvalidRecordingFormatError { format: s.to_string() }),
+        }
+    }
+}
+
+impl RecordingFormat {
+    pub fn from_path(path: &Path) -> RecordingFormat {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("y4m") => RecordingFormat::Y4m,
+            Some("raw") | Some("rgb") => RecordingFormat::Raw,
+            _ => RecordingFormat::Gif,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            RecordingFormat::Gif => "gif",
+            RecordingFormat::Raw => "rgb",
+            RecordingFormat::Y4m => "y4m",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct RecordingSettings {
+    pub format: RecordingFormat,
+    pub scale: u32,
+    // Overrides the foreground and background of the current palette
+    pub colors: Option<(Rgb, Rgb)>,
+}
+
+enum RecordingSink {
+    Gif(gif::Encoder<BufWriter<File>>),
+    Raw(BufWriter<File>),
+    Y4m(BufWriter<File>),
+}
+
+pub struct Recorder {
+    sink: RecordingSink,
+    settings: RecordingSettings,
+    frames: u64,
+}
+
+fn rgb_to_yuv([r, g, b]: Rgb) -> (u8, u8, u8) {
+    let (r, g, b) = (r as f64, g as f64, b as f64);
+    let y = 16.0 + 0.257 * r + 0.504 * g + 0.098 * b;
+    let u = 128.0 - 0.148 * r - 0.291 * g + 0.439 * b;
+    let v = 128.0 + 0.439 * r - 0.368 * g - 0.071 * b;
+
+    (y.round() as u8, u.round() as u8, v.round() as u8)
+}
+
+impl Recorder {
+    pub fn start(path: &Path, settings: RecordingSettings) -> Result<Recorder, Box<dyn Error>> {
+        let scale = settings.scale.max(1);
+        let settings = RecordingSettings { scale, ..settings };
+
+        let width = SCREEN_WIDTH as u32 * scale;
+        let height = SCREEN_HEIGHT as u32 * scale;
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        let sink = match settings.format {
+            RecordingFormat::Gif => {
+                // Every frame carries its own palette, since the palette and filter can change while recording
+                let mut encoder = gif::Encoder::new(writer, width as u16, height as u16, &[])?;
+                encoder.set_repeat(gif::Repeat::Infinite)?;
+                RecordingSink::Gif(encoder)
+            },
+            RecordingFormat::Raw => RecordingSink::Raw(writer),
+            RecordingFormat::Y4m => {
+                writeln!(writer, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444", width, height, RECORDING_FPS)?;
+                RecordingSink::Y4m(writer)
+            },
+        };
+
+        Ok(Recorder {
+            sink,
+            settings,
+            frames: 0,
+        })
+    }
+
+    fn gif_frame_delay(&self) -> u16 {
+        let elapsed = |frames: u64| (frames * 100 + RECORDING_FPS as u64 / 2) / RECORDING_FPS as u64;
+        (elapsed(self.frames + 1) - elapsed(self.frames)) as u16
+    }
+
+    fn palette(&self, palette: &Palette) -> Palette {
+        let mut palette = palette.clone();
+        if let Some((on_color, off_color)) = self.settings.colors {
+            palette.set_color(0, off_color);
+            palette.set_color(1, on_color);
+        }
+
+        palette
+    }
+
+    pub fn record_frame(&mut self, frame: &Frame, palette: &Palette) -> Result<(), Box<dyn Error>> {
+        let scale = self.settings.scale;
+        let width = SCREEN_WIDTH as u32 * scale;
+        let height = SCREEN_HEIGHT as u32 * scale;
+        let delay = self.gif_frame_delay();
+        let palette = self.palette(palette);
+
+        match &mut self.sink {
+            RecordingSink::Gif(encoder) => {
+                let mut colors: Vec<Rgb> = Vec::new();
+                let mut pixels = Vec::with_capacity((width * height) as usize);
+                for y in 0..SCREEN_HEIGHT {
+                    let mut line = Vec::with_capacity(width as usize);
+                    for x in 0..SCREEN_WIDTH {
+                        let intensity = (frame.intensity(x, y) * GIF_INTENSITY_LEVELS).round() / GIF_INTENSITY_LEVELS;
+                        let color = palette.mix(frame.planes(x, y), intensity);
+                        let index = match colors.iter().position(|known| *known == color) {
+                            Some(index) => index,
+                            None => {
+                                colors.push(color);
+                                colors.len() - 1
+                            },
+                        };
+                        for _ in 0..scale {
+                            line.push(index as u8);
+                        }
+                    }
+                    for _ in 0..scale {
+                        pixels.extend_from_slice(&line);
+                    }
+                }
+
+                let mut gif_frame = gif::Frame::from_indexed_pixels(width as u16, height as u16, &pixels, None);
+                gif_frame.palette = Some(colors.concat());
+                gif_frame.delay = delay;
+                encoder.write_frame(&gif_frame)?;
+            },
+            RecordingSink::Raw(writer) => {
+                let image = render_frame(frame, scale, &palette);
+                writer.write_all(image.data())?;
+            },
+            RecordingSink::Y4m(writer) => {
+                let image = render_frame(frame, scale, &palette);
+                let mut planes = [
+                    Vec::with_capacity((width * height) as usize),
+                    Vec::with_capacity((width * height) as usize),
+                    Vec::with_capacity((width * height) as usize),
+                ];
+                for y in 0..height {
+                    for x in 0..width {
+                        let (luma, u, v) = rgb_to_yuv(image.get_pixel(x, y));
+                        planes[0].push(luma);
+                        planes[1].push(u);
+                        planes[2].push(v);
+                    }
+                }
+
+                writer.write_all(b"FRAME\n")?;
+                for plane in planes.iter() {
+                    writer.write_all(plane)?;
+                }
+            },
+        };
+
+        self.frames += 1;
+
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<u64, Box<dyn Error>> {
+        match self.sink {
+            RecordingSink::Gif(encoder) => {
+                encoder.into_inner()?.flush()?;
+            },
+            RecordingSink::Raw(mut writer) | RecordingSink::Y4m(mut writer) => {
+                writer.flush()?;
+            },
+        };
+
+        Ok(self.frames)
+    }
+}
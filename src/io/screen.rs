@@ -1,3 +1,5 @@
+use serde::Serialize;
+
 pub const SCREEN_WIDTH: usize = 64;
 pub const SCREEN_HEIGHT: usize = 32;
 pub const MAX_SPRITE_SIZE: usize = 15;
@@ -25,12 +27,28 @@ impl std::fmt::Display for InvalidSpriteSizeError {
 
 impl std::error::Error for InvalidSpriteSizeError {}
 
+#[derive(Debug, Copy, Clone, Serialize)]
+pub struct PixelProvenance {
+    pub pc: u16,
+    pub i: u16,
+    pub frame: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScreenSnapshot {
+    pub frame: u64,
+    pub pixels: Vec<Vec<bool>>,
+    pub provenance: Option<Vec<Vec<Option<PixelProvenance>>>>,
+}
+
 pub struct Screen {
     screen: [[bool; SCREEN_WIDTH]; SCREEN_HEIGHT],
     has_changed: bool,
     last_drawn_sprite: Option<Vec<u8>>,
     last_draw_result: Option<Vec<u8>>,
     last_draw_area: Option<Vec<u8>>,
+    frame: u64,
+    pixel_provenance: Option<Vec<Option<PixelProvenance>>>,
 }
 
 impl Screen {
@@ -41,6 +59,42 @@ impl Screen {
             last_drawn_sprite: None,
             last_draw_result: None,
             last_draw_area: None,
+            frame: 0,
+            pixel_provenance: None,
+        }
+    }
+
+    pub fn enable_pixel_provenance(&mut self) {
+        if self.pixel_provenance.is_none() {
+            self.pixel_provenance = Some(vec![None; SCREEN_WIDTH * SCREEN_HEIGHT]);
+        }
+    }
+
+    pub fn tick_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    pub fn inspect_pixel_provenance(&self, x: usize, y: usize) -> Option<PixelProvenance> {
+        if x >= SCREEN_WIDTH || y >= SCREEN_HEIGHT {
+            return None;
+        }
+
+        match &self.pixel_provenance {
+            Some(provenance) => provenance[y * SCREEN_WIDTH + x],
+            None => None,
+        }
+    }
+
+    pub fn snapshot(&self) -> ScreenSnapshot {
+        let pixels = self.screen.iter().map(|row| row.to_vec()).collect();
+        let provenance = self.pixel_provenance.as_ref().map(|provenance| {
+            provenance.chunks(SCREEN_WIDTH).map(|row| row.to_vec()).collect()
+        });
+
+        ScreenSnapshot {
+            frame: self.frame,
+            pixels,
+            provenance,
         }
     }
 
@@ -59,9 +113,15 @@ impl Screen {
     pub fn clear(&mut self) {
         self.screen = [[false; SCREEN_WIDTH]; SCREEN_HEIGHT];
         self.has_changed = true;
+
+        if let Some(provenance) = &mut self.pixel_provenance {
+            for pixel in provenance.iter_mut() {
+                *pixel = None;
+            }
+        }
     }
 
-    fn draw_sprite_line(&mut self, x: u8, y: u8, sprite_line: u8) -> bool {
+    fn draw_sprite_line(&mut self, x: u8, y: u8, sprite_line: u8, pc: u16, i_val: u16) -> bool {
         let mut is_pixel_overwritten = false;
         let wrapped_y = (y as usize) % SCREEN_HEIGHT;
 
@@ -76,6 +136,17 @@ impl Screen {
             self.screen[wrapped_y][wrapped_x] = final_value;
             draw_line = draw_line | ((final_value as u8) << (7 - i));
 
+            if sprite_pixel {
+                let frame = self.frame;
+                if let Some(provenance) = &mut self.pixel_provenance {
+                    provenance[wrapped_y * SCREEN_WIDTH + wrapped_x] = Some(PixelProvenance {
+                        pc,
+                        i: i_val,
+                        frame,
+                    });
+                }
+            }
+
             is_pixel_overwritten = is_pixel_overwritten || (sprite_pixel && self.screen[wrapped_y][wrapped_x]);
         }
 
@@ -87,10 +158,10 @@ impl Screen {
         is_pixel_overwritten
     }
 
-    pub fn draw(&mut self, x: u8, y: u8, sprite: Vec<u8>) -> Result<IsPixelOverwritten, Box<dyn std::error::Error>> {
+    pub fn draw(&mut self, x: u8, y: u8, sprite: Vec<u8>, pc: u16, i_val: u16) -> Result<IsPixelOverwritten, InvalidSpriteSizeError> {
         let mut is_pixel_overwritten = false;
         if sprite.len() > MAX_SPRITE_SIZE {
-            return Err(Box::new(InvalidSpriteSizeError::new(sprite.len())));
+            return Err(InvalidSpriteSizeError::new(sprite.len()));
         }
 
         self.last_drawn_sprite = Some(sprite.clone());
@@ -98,7 +169,7 @@ impl Screen {
         self.last_draw_result = Some(vec![]);
 
         for (i, sprite_line) in sprite.iter().enumerate() {
-            let does_line_overwrite_pixel = self.draw_sprite_line(x, y + (i as u8), *sprite_line);
+            let does_line_overwrite_pixel = self.draw_sprite_line(x, y + (i as u8), *sprite_line, pc, i_val);
             is_pixel_overwritten = is_pixel_overwritten || does_line_overwrite_pixel;
         }
 
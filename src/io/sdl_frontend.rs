@@ -0,0 +1,308 @@
+use super::frontend::{Frontend, FrontendEvent, SpeedControl};
+use super::gamepad::{GamepadInput, GamepadKeyboard};
+use super::keymap::KeyMap;
+use super::keys::{CombinedKeyboard, Key, Keyboard, SDL2Keyboard};
+use super::palette::Palette;
+use super::display_filter::Frame;
+use super::screen::{Screen, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+use sdl2;
+use sdl2::controller::GameController;
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Mod, Scancode};
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::Texture;
+use sdl2::video::{FullscreenType, Window};
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_WINDOW_SCALE: u32 = 20;
+// Well beyond any display, so window sizes stay far from overflowing
+pub const MAX_WINDOW_SCALE: u32 = 256;
+const WINDOW_TITLE: &str = "rip8";
+const LETTERBOX_COLOR: Color = Color::RGB(0, 0, 0);
+const DISPLAY_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+const BYTES_PER_PIXEL: usize = 3;
+
+// Largest whole number of window pixels per CHIP-8 pixel that fits in width x height
+fn integer_scale(width: u32, height: u32) -> u32 {
+    (width / SCREEN_WIDTH as u32).min(height / SCREEN_HEIGHT as u32).max(1)
+}
+
+// The screen area centered in the output, with the remaining space left as black bars
+fn letterbox(output_width: u32, output_height: u32) -> Rect {
+    let scale = integer_scale(output_width, output_height);
+    let (width, height) = (SCREEN_WIDTH as u32 * scale, SCREEN_HEIGHT as u32 * scale);
+    let x = (output_width as i32 - width as i32) / 2;
+    let y = (output_height as i32 - height as i32) / 2;
+
+    Rect::new(x, y, width, height)
+}
+
+fn upload_frame(texture: &mut Texture, frame: &Frame, palette: &Palette) -> Result<(), Box<dyn Error + Send + Sync>> {
+    texture.with_lock(None, |pixels: &mut [u8], pitch: usize| {
+        for row in 0..SCREEN_HEIGHT {
+            for column in 0..SCREEN_WIDTH {
+                let offset = row * pitch + column * BYTES_PER_PIXEL;
+                pixels[offset..offset + BYTES_PER_PIXEL].copy_from_slice(&frame.color(column, row, palette));
+            }
+        }
+    })?;
+    Ok(())
+}
+
+// The texture is stretched over the letterboxed area, the renderer's nearest scaling keeps pixels sharp
+fn draw_to_canvas(canvas: &mut sdl2::render::WindowCanvas, texture: &Texture) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (output_width, output_height) = canvas.output_size()?;
+
+    canvas.set_draw_color(LETTERBOX_COLOR);
+    canvas.clear();
+    canvas.copy(texture, None, letterbox(output_width, output_height))?;
+    canvas.present();
+    Ok(())
+}
+
+fn desktop_size(window: &Window) -> Option<(i32, i32)> {
+    let display_index = window.display_index().ok()?;
+    let mode = window.subsystem().desktop_display_mode(display_index).ok()?;
+    Some((mode.w, mode.h))
+}
+
+// Shrinks the requested scale until the window fits on the display it is shown on
+fn fit_window(window: &mut Window, scale: u32) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let scale = match window.display_index().and_then(|index| window.subsystem().display_bounds(index)) {
+        Ok(bounds) => scale.min(integer_scale(bounds.width(), bounds.height())),
+        Err(_) => scale,
+    };
+
+    window.set_size(SCREEN_WIDTH as u32 * scale, SCREEN_HEIGHT as u32 * scale)?;
+    window.set_position(sdl2::video::WindowPos::Centered, sdl2::video::WindowPos::Centered);
+    Ok(())
+}
+
+pub struct SdlFrontend {
+    _sdl_context: sdl2::Sdl,
+    event_pump: sdl2::EventPump,
+    canvas: sdl2::render::WindowCanvas,
+    texture: Texture,
+    key_map: HashMap<Key, Vec<Scancode>>,
+    // Missing when SDL cannot start game controller support, gamepads are then ignored
+    controller_subsystem: Option<sdl2::GameControllerSubsystem>,
+    controllers: Vec<GameController>,
+    gamepad_bindings: Vec<(GamepadInput, Key)>,
+    palette: Palette,
+    scale: u32,
+    desktop_size: Option<(i32, i32)>,
+    last_display_check: Instant,
+}
+
+impl SdlFrontend {
+    pub fn new(palette: &Palette, key_map: &KeyMap, scale: u32) -> Result<SdlFrontend, Box<dyn Error + Send + Sync>> {
+        let sdl_context = sdl2::init()?;
+        let event_pump = sdl_context.event_pump()?;
+
+        let video_subsystem = sdl_context.video()?;
+        let controller_subsystem = match sdl_context.game_controller() {
+            Ok(controller_subsystem) => Some(controller_subsystem),
+            Err(error) => {
+                println!("Running without gamepads, game controller support is unavailable: {}", error);
+                None
+            },
+        };
+
+        let mut window = video_subsystem
+            .window(WINDOW_TITLE, SCREEN_WIDTH as u32 * scale, SCREEN_HEIGHT as u32 * scale)
+            .position_centered()
+            .resizable()
+            .build()?;
+        window.set_minimum_size(SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32)?;
+        fit_window(&mut window, scale)?;
+        let desktop_size = desktop_size(&window);
+
+        sdl2::hint::set("SDL_RENDER_SCALE_QUALITY", "nearest");
+        let mut canvas = window.into_canvas().build()?;
+        let mut texture = canvas
+            .texture_creator()
+            .create_texture_streaming(PixelFormatEnum::RGB24, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32)?;
+        upload_frame(&mut texture, &Frame::from_screen(&Screen::new()), palette)?;
+        draw_to_canvas(&mut canvas, &texture)?;
+
+        let mut frontend = SdlFrontend {
+            _sdl_context: sdl_context,
+            event_pump,
+            canvas,
+            texture,
+            key_map: key_map.scancodes(),
+            controller_subsystem,
+            controllers: vec![],
+            gamepad_bindings: key_map.gamepad_bindings(),
+            palette: palette.clone(),
+            scale,
+            desktop_size,
+            last_display_check: Instant::now(),
+        };
+
+        for warning in key_map.warnings() {
+            frontend.show_message(&warning);
+        }
+
+        Ok(frontend)
+    }
+
+    fn toggle_fullscreen(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let window = self.canvas.window_mut();
+        let fullscreen = match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off,
+        };
+        window.set_fullscreen(fullscreen)?;
+        Ok(())
+    }
+
+    // Resizes the window when the desktop resolution changes or the window moves to another display
+    fn check_display_mode(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let desktop_size = desktop_size(self.canvas.window());
+        if desktop_size == self.desktop_size {
+            return Ok(());
+        }
+
+        self.desktop_size = desktop_size;
+        if self.canvas.window().fullscreen_state() == FullscreenType::Off {
+            fit_window(self.canvas.window_mut(), self.scale)?;
+        }
+        Ok(())
+    }
+
+
+    fn add_controller(&mut self, joystick_index: u32) {
+        let controller_subsystem = match &self.controller_subsystem {
+            Some(controller_subsystem) => controller_subsystem,
+            None => return,
+        };
+
+        match controller_subsystem.open(joystick_index) {
+            Ok(controller) => {
+                if self.controllers.iter().any(|open| open.instance_id() == controller.instance_id()) {
+                    return;
+                }
+                println!("Connected controller {}", controller.name());
+                self.controllers.push(controller);
+            },
+            Err(error) => println!("Error while opening controller {}: {}", joystick_index, error),
+        }
+    }
+
+    fn remove_controller(&mut self, instance_id: u32) {
+        if let Some(index) = self.controllers.iter().position(|controller| controller.instance_id() == instance_id) {
+            println!("Disconnected controller {}", self.controllers.remove(index).name());
+        }
+    }
+}
+
+impl Frontend for SdlFrontend {
+    fn poll_events(&mut self) -> Vec<FrontendEvent> {
+        let mut events = vec![];
+        let sdl_events: Vec<Event> = self.event_pump.poll_iter().collect();
+        let mut needs_redraw = false;
+        for event in sdl_events {
+            match event {
+                Event::Quit {..} => events.push(FrontendEvent::Quit),
+                Event::Window { win_event: WindowEvent::SizeChanged(..), .. } |
+                Event::Window { win_event: WindowEvent::Exposed, .. } => needs_redraw = true,
+                Event::KeyDown { keycode: Some(Keycode::F11), repeat: false, .. } => {
+                    if let Err(error) = self.toggle_fullscreen() {
+                        println!("Error while toggling fullscreen: {}", error);
+                    }
+                },
+                Event::KeyDown { keycode: Some(Keycode::Return), keymod, repeat: false, .. }
+                    if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) => {
+                    if let Err(error) = self.toggle_fullscreen() {
+                        println!("Error while toggling fullscreen: {}", error);
+                    }
+                },
+                Event::ControllerDeviceAdded { which, .. } => self.add_controller(which),
+                Event::ControllerDeviceRemoved { which, .. } => self.remove_controller(which),
+                Event::KeyDown { keycode: Some(Keycode::F12), repeat: false, .. } => {
+                    events.push(FrontendEvent::Screenshot);
+                },
+                Event::KeyDown { keycode: Some(Keycode::F9), repeat: false, .. } => {
+                    events.push(FrontendEvent::ToggleRecording);
+                },
+                Event::KeyDown { keycode: Some(Keycode::F7), repeat: false, .. } => {
+                    events.push(FrontendEvent::CyclePalette);
+                },
+                Event::KeyDown { keycode: Some(Keycode::F6), repeat: false, .. } => {
+                    events.push(FrontendEvent::CycleFilter);
+                },
+                Event::KeyDown { keycode: Some(Keycode::Tab), repeat: false, .. } => {
+                    events.push(FrontendEvent::Speed(SpeedControl::FastForward(true)));
+                },
+                Event::KeyUp { keycode: Some(Keycode::Tab), .. } => {
+                    events.push(FrontendEvent::Speed(SpeedControl::FastForward(false)));
+                },
+                Event::KeyDown { keycode: Some(Keycode::Equals), .. } |
+                Event::KeyDown { keycode: Some(Keycode::KpPlus), .. } => {
+                    events.push(FrontendEvent::Speed(SpeedControl::Faster));
+                },
+                Event::KeyDown { keycode: Some(Keycode::Minus), .. } |
+                Event::KeyDown { keycode: Some(Keycode::KpMinus), .. } => {
+                    events.push(FrontendEvent::Speed(SpeedControl::Slower));
+                },
+                Event::KeyDown { keycode: Some(Keycode::Pause), repeat: false, .. } |
+                Event::KeyDown { keycode: Some(Keycode::F8), repeat: false, .. } => {
+                    events.push(FrontendEvent::Speed(SpeedControl::TogglePause));
+                },
+                Event::KeyDown { keycode: Some(Keycode::F10), .. } => {
+                    events.push(FrontendEvent::Speed(SpeedControl::AdvanceFrame));
+                },
+                _ => {},
+            };
+        }
+
+        if self.last_display_check.elapsed() >= DISPLAY_CHECK_INTERVAL {
+            self.last_display_check = Instant::now();
+            if let Err(error) = self.check_display_mode() {
+                println!("Error while resizing window: {}", error);
+            }
+        }
+
+        if needs_redraw {
+            if let Err(error) = draw_to_canvas(&mut self.canvas, &self.texture) {
+                println!("Error while redrawing window: {}", error);
+            }
+        }
+
+        events
+    }
+
+    fn keyboard(&self) -> Box<dyn Keyboard + '_> {
+        Box::new(CombinedKeyboard(
+            SDL2Keyboard::new(self.event_pump.keyboard_state(), &self.key_map),
+            GamepadKeyboard::new(&self.controllers, &self.gamepad_bindings),
+        ))
+    }
+
+    fn present(&mut self, frame: &Frame) -> Result<(), Box<dyn Error + Send + Sync>> {
+        upload_frame(&mut self.texture, frame, &self.palette)?;
+        draw_to_canvas(&mut self.canvas, &self.texture)
+    }
+
+    fn set_palette(&mut self, palette: &Palette) {
+        self.palette = palette.clone();
+    }
+
+    fn set_status(&mut self, status: Option<&str>) {
+        let title = match status {
+            Some(status) => format!("{} - {}", WINDOW_TITLE, status),
+            None => String::from(WINDOW_TITLE),
+        };
+
+        if let Err(error) = self.canvas.window_mut().set_title(&title) {
+            println!("Error while setting window title: {}", error);
+        }
+    }
+}
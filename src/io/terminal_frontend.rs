@@ -0,0 +1,304 @@
+use super::display_filter::Frame;
+use super::frontend::{Frontend, FrontendEvent};
+use super::image::Rgb;
+use super::keymap::KeyMap;
+use super::keys::{HeadlessKeyboard, Key, Keyboard};
+use super::palette::Palette;
+use super::screen::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{poll, read, Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{stdout, Stdout, Write};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+// Terminals only report key presses and auto repeats, so a key counts as held for a while after each one. The
+// first repeat usually follows the press by 250-500ms, anything shorter lets held keys drop out in between
+pub const DEFAULT_KEY_HOLD_DURATION: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TerminalRenderMode {
+    HalfBlock,
+    Braille,
+}
+
+#[derive(Debug)]
+pub struct InvalidTerminalSettingError {
+    msg: String,
+}
+
+impl std::fmt::Display for InvalidTerminalSettingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.msg)
+    }
+}
+
+impl Error for InvalidTerminalSettingError {}
+
+impl FromStr for TerminalRenderMode {
+    type Err = InvalidTerminalSettingError;
+
+    fn from_str(s: &str) -> Result<TerminalRenderMode, InvalidTerminalSettingError> {
+        match s {
+            "halfblock" | "half-block" => Ok(TerminalRenderMode::HalfBlock),
+            "braille" => Ok(TerminalRenderMode::Braille),
+            _ => Err(InvalidTerminalSettingError {
+                msg: format!("Unknown terminal render mode {}, expected halfblock or braille", s),
+            }),
+        }
+    }
+}
+
+type Cell = (char, Rgb, Rgb);
+
+pub struct TerminalFrontend {
+    mode: TerminalRenderMode,
+    key_map: HashMap<char, Key>,
+    key_presses: [Option<Instant>; 16],
+    key_hold_duration: Duration,
+    palette: Palette,
+    last_cells: Vec<Option<Cell>>,
+    origin: (u16, u16),
+    status: Option<String>,
+    messages: Vec<String>,
+    status_changed: bool,
+    stdout: Stdout,
+}
+
+fn to_terminal_color([r, g, b]: Rgb) -> Color {
+    Color::Rgb { r, g, b }
+}
+
+impl TerminalFrontend {
+    pub fn new(mode: TerminalRenderMode, key_map: &KeyMap,
+               palette: &Palette) -> Result<TerminalFrontend, Box<dyn Error + Send + Sync>> {
+        let mut stdout = stdout();
+
+        enable_raw_mode()?;
+        execute!(stdout, EnterAlternateScreen, Hide, Clear(ClearType::All))?;
+
+        let (columns, rows) = TerminalFrontend::grid_size(mode);
+
+        let mut frontend = TerminalFrontend {
+            mode,
+            key_map: key_map.chars(),
+            key_presses: [None; 16],
+            key_hold_duration: DEFAULT_KEY_HOLD_DURATION,
+            palette: palette.clone(),
+            last_cells: vec![None; columns * rows],
+            origin: (0, 0),
+            status: None,
+            messages: vec![],
+            status_changed: true,
+            stdout,
+        };
+
+        for warning in key_map.warnings() {
+            frontend.show_message(&warning);
+        }
+
+        Ok(frontend)
+    }
+
+    pub fn set_key_hold_duration(&mut self, duration: Duration) {
+        self.key_hold_duration = duration;
+    }
+
+    fn grid_size(mode: TerminalRenderMode) -> (usize, usize) {
+        match mode {
+            TerminalRenderMode::HalfBlock => (SCREEN_WIDTH, SCREEN_HEIGHT / 2),
+            TerminalRenderMode::Braille => (SCREEN_WIDTH / 2, SCREEN_HEIGHT / 4),
+        }
+    }
+
+    pub fn screen_size(&self) -> (u16, u16) {
+        let (columns, rows) = TerminalFrontend::grid_size(self.mode);
+        (columns as u16, rows as u16)
+    }
+
+    pub fn set_origin(&mut self, column: u16, row: u16) {
+        self.origin = (column, row);
+        self.invalidate();
+    }
+
+    pub fn invalidate(&mut self) {
+        for cell in self.last_cells.iter_mut() {
+            *cell = None;
+        }
+        self.status_changed = true;
+    }
+
+    // The status line sits below the screen and the border the debugger draws around it
+    fn draw_status_line(&mut self) -> crossterm::Result<()> {
+        let (columns, rows) = TerminalFrontend::grid_size(self.mode);
+        let width = columns + self.origin.0 as usize * 2;
+        let parts: Vec<&str> = self.status.iter().chain(self.messages.last()).map(String::as_str).collect();
+        let line: String = parts.join(" - ").chars().take(width).collect();
+
+        queue!(
+            self.stdout,
+            ResetColor,
+            MoveTo(0, self.origin.1 * 2 + rows as u16),
+            Print(format!("{:<width$}", line, width = width))
+        )?;
+        self.status_changed = false;
+        Ok(())
+    }
+
+    fn redraw_status_line(&mut self) {
+        self.status_changed = true;
+        let _ = self.draw_status_line().and_then(|_| self.stdout.flush().map_err(Into::into));
+    }
+
+    pub fn handle_event(&mut self, event: Event) -> Option<FrontendEvent> {
+        match event {
+            Event::Key(KeyEvent { code: KeyCode::Esc, .. }) => Some(FrontendEvent::Quit),
+            Event::Key(KeyEvent { code: KeyCode::Char('c'), modifiers }) if modifiers.contains(KeyModifiers::CONTROL) => {
+                Some(FrontendEvent::Quit)
+            },
+            Event::Key(KeyEvent { code: KeyCode::F(12), .. }) => Some(FrontendEvent::Screenshot),
+            Event::Key(KeyEvent { code: KeyCode::F(9), .. }) => Some(FrontendEvent::ToggleRecording),
+            Event::Key(KeyEvent { code: KeyCode::F(7), .. }) => Some(FrontendEvent::CyclePalette),
+            Event::Key(KeyEvent { code: KeyCode::F(6), .. }) => Some(FrontendEvent::CycleFilter),
+            Event::Key(KeyEvent { code: KeyCode::Char(c), .. }) => {
+                self.press_key(c);
+                None
+            },
+            Event::Resize(_, _) => {
+                self.invalidate();
+                let _ = execute!(self.stdout, Clear(ClearType::All));
+                None
+            },
+            _ => None,
+        }
+    }
+
+    fn cell(&self, frame: &Frame, column: usize, row: usize) -> Cell {
+        match self.mode {
+            TerminalRenderMode::HalfBlock => {
+                let top = frame.color(column, row * 2, &self.palette);
+                let bottom = frame.color(column, row * 2 + 1, &self.palette);
+                ('\u{2580}', top, bottom)
+            },
+            TerminalRenderMode::Braille => {
+                const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+                // All dots of a cell share one color, taken from the planes of its lit dots
+                let mut bits = 0;
+                let mut planes = 1;
+                for (dy, dot_row) in DOTS.iter().enumerate() {
+                    for (dx, dot) in dot_row.iter().enumerate() {
+                        if frame.is_lit(column * 2 + dx, row * 4 + dy) {
+                            bits |= dot;
+                            planes = planes.max(frame.planes(column * 2 + dx, row * 4 + dy));
+                        }
+                    }
+                }
+
+                let glyph = std::char::from_u32(0x2800 + bits).unwrap_or(' ');
+                (glyph, self.palette.plane_color(planes), self.palette.background())
+            },
+        }
+    }
+
+    fn press_key(&mut self, c: char) {
+        if let Some(key) = self.key_map.get(&c.to_ascii_lowercase()) {
+            self.key_presses[*key as usize] = Some(Instant::now());
+        }
+    }
+}
+
+impl Frontend for TerminalFrontend {
+    fn poll_events(&mut self) -> Vec<FrontendEvent> {
+        let mut events = vec![];
+
+        while let Ok(true) = poll(Duration::from_millis(0)) {
+            let event = match read() {
+                Ok(event) => event,
+                Err(_) => break,
+            };
+
+            if let Some(event) = self.handle_event(event) {
+                events.push(event);
+            }
+        }
+
+        events
+    }
+
+    fn keyboard(&self) -> Box<dyn Keyboard + '_> {
+        let mut keyboard = HeadlessKeyboard::new();
+        for (key, pressed_at) in self.key_presses.iter().enumerate() {
+            if let Some(pressed_at) = pressed_at {
+                keyboard.set_key_pressed(key as Key, pressed_at.elapsed() < self.key_hold_duration);
+            }
+        }
+
+        Box::new(keyboard)
+    }
+
+    fn present(&mut self, frame: &Frame) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (columns, rows) = TerminalFrontend::grid_size(self.mode);
+
+        for row in 0..rows {
+            for column in 0..columns {
+                let cell = self.cell(frame, column, row);
+                let index = row * columns + column;
+                if self.last_cells[index] == Some(cell) {
+                    continue;
+                }
+
+                let (glyph, foreground, background) = cell;
+                queue!(
+                    self.stdout,
+                    MoveTo(self.origin.0 + column as u16, self.origin.1 + row as u16),
+                    SetForegroundColor(to_terminal_color(foreground)),
+                    SetBackgroundColor(to_terminal_color(background)),
+                    Print(glyph)
+                )?;
+                self.last_cells[index] = Some(cell);
+            }
+        }
+
+        queue!(self.stdout, ResetColor)?;
+        if self.status_changed {
+            self.draw_status_line()?;
+        }
+        self.stdout.flush()?;
+
+        Ok(())
+    }
+
+    fn set_palette(&mut self, palette: &Palette) {
+        self.palette = palette.clone();
+        self.invalidate();
+    }
+
+    fn set_status(&mut self, status: Option<&str>) {
+        self.status = status.map(String::from);
+        self.redraw_status_line();
+    }
+
+    fn show_message(&mut self, message: &str) {
+        self.messages.push(message.to_string());
+        self.redraw_status_line();
+    }
+}
+
+impl Drop for TerminalFrontend {
+    fn drop(&mut self) {
+        let _ = execute!(self.stdout, ResetColor, Show, LeaveAlternateScreen);
+        let _ = disable_raw_mode();
+
+        // The status line goes away with the alternate screen, so everything it showed is printed again
+        for message in self.messages.iter() {
+            println!("{}", message);
+        }
+    }
+}
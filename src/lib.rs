@@ -0,0 +1,7 @@
+#![feature(proc_macro_hygiene, decl_macro)]
+#[macro_use] extern crate rocket;
+
+pub mod mem;
+pub mod logic;
+pub mod exec;
+pub mod io;
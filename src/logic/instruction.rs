@@ -46,3 +46,86 @@ pub enum Instruction {
     LDARR(VRegister),
     RDARR(VRegister),
 }
+
+impl Instruction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Instruction::CLS => "CLS",
+            Instruction::RET => "RET",
+            Instruction::JP(_) => "JP",
+            Instruction::CALL(_) => "CALL",
+            Instruction::SE(_, _) => "SE",
+            Instruction::SNE(_, _) => "SNE",
+            Instruction::LD(_, _) => "LD",
+            Instruction::ADD(_, _) => "ADD",
+            Instruction::ADDI(_) => "ADDI",
+            Instruction::OR(_, _) => "OR",
+            Instruction::AND(_, _) => "AND",
+            Instruction::XOR(_, _) => "XOR",
+            Instruction::SUB(_, _) => "SUB",
+            Instruction::SHR(_) => "SHR",
+            Instruction::SUBN(_, _) => "SUBN",
+            Instruction::SHL(_) => "SHL",
+            Instruction::LDI(_) => "LDI",
+            Instruction::JPV0(_) => "JPV0",
+            Instruction::RND(_, _) => "RND",
+            Instruction::DRW(_, _, _) => "DRW",
+            Instruction::SKP(_) => "SKP",
+            Instruction::SKNP(_) => "SKNP",
+            Instruction::LDK(_) => "LDK",
+            Instruction::LDF(_) => "LDF",
+            Instruction::LD_TO_DT(_) => "LD_TO_DT",
+            Instruction::LD_FROM_DT(_) => "LD_FROM_DT",
+            Instruction::LDST(_) => "LDST",
+            Instruction::LDBCD(_) => "LDBCD",
+            Instruction::LDARR(_) => "LDARR",
+            Instruction::RDARR(_) => "RDARR",
+        }
+    }
+}
+
+impl std::fmt::Display for ByteOrVReg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ByteOrVReg::Byte(byte) => write!(f, "0x{:02X}", byte),
+            ByteOrVReg::Register(reg) => write!(f, "{}", reg.name()),
+        }
+    }
+}
+
+impl std::fmt::Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Instruction::CLS => write!(f, "CLS"),
+            Instruction::RET => write!(f, "RET"),
+            Instruction::JP(addr) => write!(f, "JP 0x{:03X}", addr),
+            Instruction::CALL(addr) => write!(f, "CALL 0x{:03X}", addr),
+            Instruction::SE(reg, val) => write!(f, "SE {}, {}", reg.name(), val),
+            Instruction::SNE(reg, val) => write!(f, "SNE {}, {}", reg.name(), val),
+            Instruction::LD(reg, val) => write!(f, "LD {}, {}", reg.name(), val),
+            Instruction::ADD(reg, val) => write!(f, "ADD {}, {}", reg.name(), val),
+            Instruction::ADDI(reg) => write!(f, "ADD I, {}", reg.name()),
+            Instruction::OR(reg_a, reg_b) => write!(f, "OR {}, {}", reg_a.name(), reg_b.name()),
+            Instruction::AND(reg_a, reg_b) => write!(f, "AND {}, {}", reg_a.name(), reg_b.name()),
+            Instruction::XOR(reg_a, reg_b) => write!(f, "XOR {}, {}", reg_a.name(), reg_b.name()),
+            Instruction::SUB(reg_a, reg_b) => write!(f, "SUB {}, {}", reg_a.name(), reg_b.name()),
+            Instruction::SHR(reg) => write!(f, "SHR {}", reg.name()),
+            Instruction::SUBN(reg_a, reg_b) => write!(f, "SUBN {}, {}", reg_a.name(), reg_b.name()),
+            Instruction::SHL(reg) => write!(f, "SHL {}", reg.name()),
+            Instruction::LDI(addr) => write!(f, "LD I, 0x{:03X}", addr),
+            Instruction::JPV0(addr) => write!(f, "JP V0, 0x{:03X}", addr),
+            Instruction::RND(reg, byte) => write!(f, "RND {}, 0x{:02X}", reg.name(), byte),
+            Instruction::DRW(reg_a, reg_b, n) => write!(f, "DRW {}, {}, {}", reg_a.name(), reg_b.name(), n),
+            Instruction::SKP(reg) => write!(f, "SKP {}", reg.name()),
+            Instruction::SKNP(reg) => write!(f, "SKNP {}", reg.name()),
+            Instruction::LDK(reg) => write!(f, "LD {}, K", reg.name()),
+            Instruction::LDF(reg) => write!(f, "LD F, {}", reg.name()),
+            Instruction::LD_TO_DT(reg) => write!(f, "LD DT, {}", reg.name()),
+            Instruction::LD_FROM_DT(reg) => write!(f, "LD {}, DT", reg.name()),
+            Instruction::LDST(reg) => write!(f, "LD ST, {}", reg.name()),
+            Instruction::LDBCD(reg) => write!(f, "LD B, {}", reg.name()),
+            Instruction::LDARR(reg) => write!(f, "LD [I], {}", reg.name()),
+            Instruction::RDARR(reg) => write!(f, "LD {}, [I]", reg.name()),
+        }
+    }
+}
@@ -1,16 +1,23 @@
-#![feature(proc_macro_hygiene, decl_macro)]
-#[macro_use] extern crate rocket;
+use rip8::exec::runtime::Runtime;
+use rip8::exec::quirks::Quirks;
+use rip8::exec::scheduler::{ parse_clock_speed, Timing, DEFAULT_CLOCK_SPEED };
+use rip8::exec::trace::TraceFormat;
+use rip8::exec::trace_diff::{ parse_reference_trace, run_trace_diff, TraceDiffResult };
+use rip8::exec::headless::DEFAULT_INSTRUCTIONS_PER_FRAME;
+use rip8::exec::rom_test::{ count_mismatches, pixels_from_golden, render_diff, run_rom_test, KeyPress };
+use rip8::io::image::{ parse_hex_color, parse_scale, render_screen, RgbImage, MAX_SCREENSHOT_SCALE, MONOCHROME_OFF, MONOCHROME_ON };
+use rip8::io::recording::{ RecordingFormat, MAX_RECORDING_SCALE };
+use rip8::io::palette::{ Palette, DEFAULT_PALETTE };
+use rip8::io::display_filter::DisplayFilter;
+use rip8::io::sdl_frontend::{ SdlFrontend, DEFAULT_WINDOW_SCALE, MAX_WINDOW_SCALE };
+use rip8::io::keymap::{ rom_keymap_path, KeyMap, DEFAULT_LAYOUT };
+use rip8::io::terminal_frontend::TerminalFrontend;
 
-mod mem;
-mod logic;
-mod exec;
-mod io;
+use clap::{ App, AppSettings, Arg, ArgMatches, SubCommand };
 
-use exec::runtime::Runtime;
-
-use clap::{ App, Arg };
-
-use std::fs::read;
+use std::fs::{ read, read_to_string };
+use std::path::{ Path, PathBuf };
+use std::time::Duration;
 
 fn main() -> Result<(), Box<dyn std::error::Error + 'static>> {
 
@@ -18,6 +25,7 @@ fn main() -> Result<(), Box<dyn std::error::Error + 'static>> {
         .version("1.0")
         .author("Steven Pham")
         .about("CHIP-8 Emulator written in rust")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(Arg::with_name("rom")
              .help("Path to CHIP-8 ROM file (.ch8)")
              .value_name("ROM_FILE")
@@ -29,16 +37,221 @@ fn main() -> Result<(), Box<dyn std::error::Error + 'static>> {
               .help("CPU clock speed (defaults to 500 Hz)")
               .value_name("HZ")
               .takes_value(true)
+        ).arg(Arg::with_name("ipf")
+              .long("ipf")
+              .help("Run a fixed number of instructions per 60 Hz frame and sleep until the next frame, instead of following --clock-speed")
+              .value_name("INSTRUCTIONS")
+              .takes_value(true)
+              .conflicts_with("clock-speed")
+        ).arg(Arg::with_name("timing")
+              .long("timing")
+              .help("Timing model, clock (--clock-speed), frames (--ipf) or vip (COSMAC VIP machine cycles at 1.76 MHz)")
+              .value_name("MODEL")
+              .takes_value(true)
+              .possible_values(&["clock", "frames", "vip"])
         ).arg(Arg::with_name("debug")
               .short("d")
               .long("debug")
-              .help("Enabled debugger window")
+              .help("Enabled debugger window, or the terminal debugger with --frontend terminal")
+        ).arg(Arg::with_name("frontend")
+              .long("frontend")
+              .help("Frontend used to display the screen and read keys, sdl or terminal (defaults to sdl)")
+              .value_name("FRONTEND")
+              .possible_values(&["sdl", "terminal"])
+              .takes_value(true)
+        ).arg(Arg::with_name("terminal-render")
+              .long("terminal-render")
+              .help("Terminal frontend rendering, halfblock or braille (defaults to halfblock)")
+              .value_name("MODE")
+              .takes_value(true)
+        ).arg(Arg::with_name("terminal-key-hold")
+              .long("terminal-key-hold")
+              .help("Milliseconds a key counts as held after each press or auto repeat in the terminal frontend (defaults to 500)")
+              .value_name("MS")
+              .takes_value(true)
+        ).arg(Arg::with_name("keymap")
+              .long("keymap")
+              .help("Keyboard layout (qwerty, azerty, qwertz, dvorak or colemak) or keymap file (defaults to qwerty)")
+              .value_name("LAYOUT|FILE")
+              .takes_value(true)
+        ).arg(Arg::with_name("terminal-keymap")
+              .long("terminal-keymap")
+              .help("Terminal frontend keys for 123C 456D 789E A0BF in order, overrides the keyboard keys of --keymap in the terminal")
+              .value_name("KEYS")
+              .takes_value(true)
+        ).arg(Arg::with_name("trace-file")
+              .long("trace-file")
+              .help("Write the instruction trace to FILE on exit")
+              .value_name("FILE")
+              .takes_value(true)
+        ).arg(Arg::with_name("trace-format")
+              .long("trace-format")
+              .help("Instruction trace file format, text or jsonl (defaults to the file extension)")
+              .value_name("FORMAT")
+              .takes_value(true)
+        ).arg(Arg::with_name("trace-capacity")
+              .long("trace-capacity")
+              .help("Maximum number of instruction trace entries kept (defaults to 4096)")
+              .value_name("ENTRIES")
+              .takes_value(true)
+        ).arg(Arg::with_name("screenshot-scale")
+              .long("screenshot-scale")
+              .help("Scale of screenshots taken with F12, up to 64 (defaults to 10)")
+              .value_name("SCALE")
+              .takes_value(true)
+        ).arg(Arg::with_name("screenshot-dir")
+              .long("screenshot-dir")
+              .help("Directory screenshots, recordings and crash dumps are written to (defaults to the current directory)")
+              .value_name("DIR")
+              .takes_value(true)
+        ).arg(Arg::with_name("record")
+              .long("record")
+              .help("Record gameplay to FILE from startup, F9 starts and stops recording at any time")
+              .value_name("FILE")
+              .takes_value(true)
+        ).arg(Arg::with_name("record-format")
+              .long("record-format")
+              .help("Recording format, gif, raw (rgb24 frames) or y4m (defaults to the file extension)")
+              .value_name("FORMAT")
+              .takes_value(true)
+        ).arg(Arg::with_name("record-scale")
+              .long("record-scale")
+              .help("Scale of recorded frames, up to 64 (defaults to 4)")
+              .value_name("SCALE")
+              .takes_value(true)
+        ).arg(Arg::with_name("fast-forward")
+              .long("fast-forward")
+              .help("Speed while Tab is held, a multiplier such as 4 or uncapped (defaults to uncapped)")
+              .value_name("SPEED")
+              .takes_value(true)
+        ).arg(Arg::with_name("scale")
+              .long("scale")
+              .help("Window pixels per CHIP-8 pixel, up to 256 (defaults to 20), F11 or Alt+Enter toggles fullscreen")
+              .value_name("SCALE")
+              .takes_value(true)
+        ).arg(Arg::with_name("palette")
+              .long("palette")
+              .help("Palette name (rip8, classic-green, amber, lcd, high-contrast or monochrome), hex colors starting with the background, or palette file")
+              .value_name("PALETTE")
+              .takes_value(true)
+        ).arg(Arg::with_name("filter")
+              .long("filter")
+              .help("Display filter, vblank, none, phosphor or blend (defaults to vblank)")
+              .value_name("FILTER")
+              .takes_value(true)
+        ).arg(Arg::with_name("phosphor-decay")
+              .long("phosphor-decay")
+              .help("Brightness kept by unlit pixels each frame with the phosphor filter (defaults to 0.6)")
+              .value_name("DECAY")
+              .takes_value(true)
+        ).arg(Arg::with_name("blend-frames")
+              .long("blend-frames")
+              .help("Number of frames averaged by the blend filter (defaults to 3)")
+              .value_name("FRAMES")
+              .takes_value(true)
+        ).arg(Arg::with_name("quirks")
+              .long("quirks")
+              .help("Comma separated emulation quirks: key-wait-held, display-wait, memory-wrap, memory-ignore")
+              .value_name("QUIRKS")
+              .takes_value(true)
+        ).arg(Arg::with_name("record-colors")
+              .long("record-colors")
+              .help("Recording colors for on and off pixels, e.g. FFFFFF,000000")
+              .value_name("ON,OFF")
+              .takes_value(true)
+        ).subcommand(SubCommand::with_name("trace-diff")
+              .about("Compares the execution of a ROM against a reference trace from another emulator")
+              .arg(Arg::with_name("rom")
+                   .help("Path to CHIP-8 ROM file (.ch8)")
+                   .value_name("ROM_FILE")
+                   .required(true)
+              ).arg(Arg::with_name("reference")
+                   .help("Path to the reference trace, one PC=XXXX OP=XXXX V0=XX .. I=XXXX SP=XX line per step")
+                   .value_name("REFERENCE")
+                   .required(true)
+              ).arg(Arg::with_name("ipf")
+                   .long("ipf")
+                   .help("Instructions executed per 60 Hz timer tick (defaults to 8)")
+                   .value_name("INSTRUCTIONS")
+                   .takes_value(true)
+              ).arg(Arg::with_name("history")
+                   .long("history")
+                   .help("Number of steps shown before a divergence (defaults to 16)")
+                   .value_name("STEPS")
+                   .takes_value(true)
+              ).arg(Arg::with_name("quirks")
+                   .long("quirks")
+                   .help("Comma separated emulation quirks: key-wait-held, display-wait, memory-wrap, memory-ignore")
+                   .value_name("QUIRKS")
+                   .takes_value(true)
+              )
+        ).subcommand(SubCommand::with_name("test")
+              .about("Runs a ROM without a window and compares the final screen against a golden image")
+              .arg(Arg::with_name("rom")
+                   .help("Path to CHIP-8 ROM file (.ch8)")
+                   .value_name("ROM_FILE")
+                   .required(true)
+              ).arg(Arg::with_name("frames")
+                   .long("frames")
+                   .help("Number of 60 Hz frames to run before comparing")
+                   .value_name("N")
+                   .takes_value(true)
+                   .required(true)
+              ).arg(Arg::with_name("expect")
+                   .long("expect")
+                   .help("Golden image, white pixels are on and black pixels are off")
+                   .value_name("PNG")
+                   .takes_value(true)
+                   .required(true)
+              ).arg(Arg::with_name("ipf")
+                   .long("ipf")
+                   .help("Instructions executed per frame (defaults to 8)")
+                   .value_name("INSTRUCTIONS")
+                   .takes_value(true)
+              ).arg(Arg::with_name("press")
+                   .long("press")
+                   .help("Holds a key during the given frames, e.g. 5@10 or A@30-40")
+                   .value_name("KEY@FRAMES")
+                   .takes_value(true)
+                   .multiple(true)
+                   .number_of_values(1)
+              ).arg(Arg::with_name("diff")
+                   .long("diff")
+                   .help("Where to write the diff image on mismatch (defaults to <PNG>.diff.png)")
+                   .value_name("PNG")
+                   .takes_value(true)
+              ).arg(Arg::with_name("update")
+                   .long("update")
+                   .help("Overwrite the golden image with the final screen instead of comparing")
+              ).arg(Arg::with_name("quirks")
+                   .long("quirks")
+                   .help("Comma separated emulation quirks: key-wait-held, display-wait, memory-wrap, memory-ignore")
+                   .value_name("QUIRKS")
+                   .takes_value(true)
+              )
         ).get_matches();
 
+    if let Some(matches) = matches.subcommand_matches("trace-diff") {
+        return trace_diff(matches);
+    }
+
+    if let Some(matches) = matches.subcommand_matches("test") {
+        return test_rom(matches);
+    }
+
     let rom_path = matches.value_of("rom").unwrap();
-    let clock_speed: f64 = match matches.value_of("clock-speed").unwrap_or("500.0").parse() {
-        Ok(v) => v,
-        Err(error) => panic!("Error while parsing clock speed: {}", error),
+    let timing_model = match matches.value_of("timing") {
+        Some(model) => model,
+        None if matches.is_present("ipf") => "frames",
+        None => "clock",
+    };
+    let timing = match timing_model {
+        "vip" => Timing::CosmacVip,
+        "frames" => {
+            let ipf = matches.value_of("ipf").map(|ipf| ipf.parse::<u32>()).unwrap_or(Ok(DEFAULT_INSTRUCTIONS_PER_FRAME))?;
+            Timing::InstructionsPerFrame(ipf.max(1))
+        },
+        _ => Timing::ClockSpeed(matches.value_of("clock-speed").map(parse_clock_speed).unwrap_or(Ok(DEFAULT_CLOCK_SPEED))?),
     };
 
     let debug = matches.is_present("debug");
@@ -47,12 +260,199 @@ fn main() -> Result<(), Box<dyn std::error::Error + 'static>> {
 
     let mut runtime = Runtime::new();
 
-    if debug {
-        runtime.start_debug(file_bytes, clock_speed)?;
+    if let Some(quirks) = matches.value_of("quirks") {
+        runtime.set_quirks(quirks.parse()?);
+    }
+
+    let mut key_map = KeyMap::load(matches.value_of("keymap").unwrap_or(DEFAULT_LAYOUT))?;
+    if let Some(keys) = matches.value_of("terminal-keymap") {
+        key_map.set_terminal_keys(keys)?;
+    }
+    if let Some(path) = rom_keymap_path(Path::new(rom_path)) {
+        println!("Applying keymap overrides from {}", path.display());
+        key_map.apply_file(&path)?;
+    }
+    for key in key_map.unbound_keys() {
+        println!("CHIP-8 key {:X} is not mapped to any key", key);
+    }
+    runtime.set_key_map(key_map.clone());
+
+    if let Some(capacity) = matches.value_of("trace-capacity") {
+        runtime.set_trace_capacity(capacity.parse()?);
+    }
+
+    if let Some(scale) = matches.value_of("screenshot-scale") {
+        runtime.set_screenshot_scale(parse_scale(scale, MAX_SCREENSHOT_SCALE)?);
+    }
+
+    if let Some(directory) = matches.value_of("screenshot-dir") {
+        runtime.set_screenshot_directory(PathBuf::from(directory));
+    }
+
+    if let Some(scale) = matches.value_of("record-scale") {
+        runtime.set_recording_scale(parse_scale(scale, MAX_RECORDING_SCALE)?);
+    }
+
+    let window_scale = match matches.value_of("scale") {
+        Some(scale) => parse_scale(scale, MAX_WINDOW_SCALE)?,
+        None => DEFAULT_WINDOW_SCALE,
+    };
+    runtime.set_window_scale(window_scale);
+
+    if let Some(speed) = matches.value_of("fast-forward") {
+        runtime.set_fast_forward_speed(speed.parse()?);
+    }
+
+    let palette = Palette::load(matches.value_of("palette").unwrap_or(DEFAULT_PALETTE))?;
+    runtime.set_palette(palette.clone());
+
+    let mut display_filter = DisplayFilter::new(matches.value_of("filter").unwrap_or("vblank").parse()?);
+    if let Some(decay) = matches.value_of("phosphor-decay") {
+        display_filter.set_phosphor_decay(decay.parse()?);
+    }
+    if let Some(frames) = matches.value_of("blend-frames") {
+        display_filter.set_blend_frames(frames.parse()?);
+    }
+    runtime.set_display_filter(display_filter);
+
+    if let Some(colors) = matches.value_of("record-colors") {
+        let mut colors = colors.splitn(2, ',');
+        let on_color = parse_hex_color(colors.next().unwrap_or(""))?;
+        let off_color = parse_hex_color(colors.next().unwrap_or(""))?;
+        runtime.set_recording_colors(on_color, off_color);
+    }
+
+    if let Some(format) = matches.value_of("record-format") {
+        runtime.set_recording_format(format.parse()?);
+    }
+
+    if let Some(record_path) = matches.value_of("record") {
+        let record_path = PathBuf::from(record_path);
+        if !matches.is_present("record-format") {
+            runtime.set_recording_format(RecordingFormat::from_path(&record_path));
+        }
+        runtime.record_on_start(record_path);
+    }
+
+    if let Some(trace_file) = matches.value_of("trace-file") {
+        let trace_format = match matches.value_of("trace-format") {
+            Some(format) => format.parse()?,
+            None => match Path::new(trace_file).extension().and_then(|ext| ext.to_str()) {
+                Some("jsonl") => TraceFormat::JsonLines,
+                _ => TraceFormat::Text,
+            },
+        };
+        runtime.export_trace_on_exit(PathBuf::from(trace_file), trace_format);
+    }
+
+    let frontend = matches.value_of("frontend").unwrap_or("sdl");
+
+    if frontend == "terminal" {
+        let mode = matches.value_of("terminal-render").unwrap_or("halfblock").parse()?;
+        let mut frontend = match TerminalFrontend::new(mode, &key_map, &palette) {
+            Ok(frontend) => frontend,
+            Err(error) => return Err(error),
+        };
+        if let Some(duration) = matches.value_of("terminal-key-hold") {
+            frontend.set_key_hold_duration(Duration::from_millis(duration.parse()?));
+        }
+
+        if debug {
+            runtime.start_terminal_debug(&mut frontend, file_bytes, timing)?;
+        } else {
+            runtime.start(&mut frontend, file_bytes, timing)?;
+        }
+    } else if debug {
+        runtime.start_debug(file_bytes, timing)?;
     } else {
-        runtime.start(file_bytes, clock_speed)?;
+        let mut frontend = match SdlFrontend::new(&palette, &key_map, window_scale) {
+            Ok(frontend) => frontend,
+            Err(error) => return Err(error),
+        };
+        runtime.start(&mut frontend, file_bytes, timing)?;
     }
 
     Ok(())
 
 }
+
+fn trace_diff(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error + 'static>> {
+    let rom_path = matches.value_of("rom").unwrap();
+    let reference_path = matches.value_of("reference").unwrap();
+
+    let instructions_per_frame: u32 = match matches.value_of("ipf") {
+        Some(ipf) => ipf.parse()?,
+        None => DEFAULT_INSTRUCTIONS_PER_FRAME,
+    };
+    let history: usize = matches.value_of("history").unwrap_or("16").parse()?;
+    let quirks: Quirks = matches.value_of("quirks").unwrap_or("").parse()?;
+
+    let program = read(rom_path)?;
+    let reference = parse_reference_trace(&read_to_string(reference_path)?)?;
+
+    match run_trace_diff(program, &reference, instructions_per_frame, history, quirks)? {
+        TraceDiffResult::Matched(steps) => {
+            println!("{} steps matched the reference trace", steps);
+            Ok(())
+        },
+        TraceDiffResult::Diverged(report) => {
+            print!("{}", report);
+            std::process::exit(1);
+        },
+    }
+}
+
+fn test_rom(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error + 'static>> {
+    let rom_path = matches.value_of("rom").unwrap();
+    let golden_path = Path::new(matches.value_of("expect").unwrap());
+    let frames: u64 = matches.value_of("frames").unwrap().parse()?;
+
+    let instructions_per_frame: u32 = match matches.value_of("ipf") {
+        Some(ipf) => ipf.parse()?,
+        None => DEFAULT_INSTRUCTIONS_PER_FRAME,
+    };
+
+    let quirks: Quirks = matches.value_of("quirks").unwrap_or("").parse()?;
+
+    let mut key_presses = vec![];
+    if let Some(presses) = matches.values_of("press") {
+        for press in presses {
+            key_presses.push(press.parse::<KeyPress>()?);
+        }
+    }
+
+    let program = read(rom_path)?;
+    let runner = match run_rom_test(program, frames, instructions_per_frame, &key_presses, quirks) {
+        Ok(runner) => runner,
+        Err(error) => {
+            println!("FAIL: {}", error);
+            std::process::exit(1);
+        },
+    };
+
+    if runner.halted() {
+        println!("Program halted at {:03X} after {} frames", runner.cpu().inspect_register_file().PC, runner.frames());
+    }
+
+    if matches.is_present("update") {
+        render_screen(runner.screen(), 1, MONOCHROME_ON, MONOCHROME_OFF).save_png(golden_path)?;
+        println!("Wrote {}", golden_path.display());
+        return Ok(());
+    }
+
+    let expected = pixels_from_golden(&RgbImage::load_png(golden_path)?)?;
+    let mismatches = count_mismatches(runner.screen(), &expected);
+    if mismatches == 0 {
+        println!("PASS: screen matches {} after {} frames", golden_path.display(), frames);
+        return Ok(());
+    }
+
+    let diff_path = match matches.value_of("diff") {
+        Some(path) => PathBuf::from(path),
+        None => golden_path.with_extension("diff.png"),
+    };
+    render_diff(runner.screen(), &expected).save_png(&diff_path)?;
+
+    println!("FAIL: {} pixels differ from {}, diff written to {}", mismatches, golden_path.display(), diff_path.display());
+    std::process::exit(1);
+}
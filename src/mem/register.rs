@@ -35,7 +35,7 @@ pub enum OtherRegister {
     I,
 }
 
-#[derive(Debug, Copy, Clone, Serialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
 pub struct RegisterFile {
     v0: u8,
     v1: u8,
@@ -128,3 +128,80 @@ impl RegisterFile {
         }
     }
 }
+
+#[derive(Debug, Copy, Clone, Serialize)]
+pub struct RegisterDelta {
+    pub register: &'static str,
+    pub old: u16,
+    pub new: u16,
+}
+
+pub const V_REGISTERS: [VRegister; 16] = [
+    VRegister::V0,
+    VRegister::V1,
+    VRegister::V2,
+    VRegister::V3,
+    VRegister::V4,
+    VRegister::V5,
+    VRegister::V6,
+    VRegister::V7,
+    VRegister::V8,
+    VRegister::V9,
+    VRegister::VA,
+    VRegister::VB,
+    VRegister::VC,
+    VRegister::VD,
+    VRegister::VE,
+    VRegister::VF,
+];
+
+impl VRegister {
+    pub fn name(&self) -> &'static str {
+        match self {
+            VRegister::V0 => "V0",
+            VRegister::V1 => "V1",
+            VRegister::V2 => "V2",
+            VRegister::V3 => "V3",
+            VRegister::V4 => "V4",
+            VRegister::V5 => "V5",
+            VRegister::V6 => "V6",
+            VRegister::V7 => "V7",
+            VRegister::V8 => "V8",
+            VRegister::V9 => "V9",
+            VRegister::VA => "VA",
+            VRegister::VB => "VB",
+            VRegister::VC => "VC",
+            VRegister::VD => "VD",
+            VRegister::VE => "VE",
+            VRegister::VF => "VF",
+        }
+    }
+}
+
+impl RegisterFile {
+    pub fn deltas(&self, after: &RegisterFile) -> Vec<RegisterDelta> {
+        let mut deltas = vec![];
+
+        for reg in V_REGISTERS.iter() {
+            let old = self.get_v_register(*reg);
+            let new = after.get_v_register(*reg);
+            if old != new {
+                deltas.push(RegisterDelta { register: reg.name(), old: old as u16, new: new as u16 });
+            }
+        }
+
+        let others = [
+            ("I", self.I, after.I),
+            ("SP", self.SP as u16, after.SP as u16),
+            ("DT", self.DT as u16, after.DT as u16),
+            ("ST", self.ST as u16, after.ST as u16),
+        ];
+        for (register, old, new) in others.iter() {
+            if old != new {
+                deltas.push(RegisterDelta { register, old: *old, new: *new });
+            }
+        }
+
+        deltas
+    }
+}
@@ -239,3 +239,33 @@ step_next_draw_button_element.onclick = () => {
     step_next_draw_request.open("POST", host + "/step-next-draw");
     step_next_draw_request.send();
 }
+
+let save_screenshot_button_element = document.getElementById("save_screenshot");
+save_screenshot_button_element.onclick = () => {
+    let save_screenshot_request = new XMLHttpRequest();
+
+    save_screenshot_request.open("POST", host + "/screenshot");
+    save_screenshot_request.send();
+}
+
+let provenance_x_element = document.getElementById("provenance_x");
+let provenance_y_element = document.getElementById("provenance_y");
+let pixel_provenance_element = document.getElementById("pixel_provenance");
+
+let inspect_pixel_provenance_element = document.getElementById("inspect_pixel_provenance");
+inspect_pixel_provenance_element.onclick = () => {
+    let pixel_provenance_request = new XMLHttpRequest();
+
+    pixel_provenance_request.onload = () => {
+        pixel_provenance_element.textContent = JSON.stringify(JSON.parse(pixel_provenance_request.response), (key, value) => {
+            if (typeof value === "number" && key !== "frame") {
+                return "0x" + value.toString(16);
+            }
+
+            return value;
+        });
+    }
+
+    pixel_provenance_request.open("GET", host + "/pixel-provenance/" + provenance_x_element.value + "/" + provenance_y_element.value);
+    pixel_provenance_request.send();
+}
@@ -0,0 +1,159 @@
+mod common;
+
+use common::{ assemble, runner };
+use rip8::exec::block_compiler::MAX_BLOCK_INSTRUCTIONS;
+use rip8::exec::headless::{ Backend, HeadlessRunner };
+use rip8::exec::quirks::Quirks;
+
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+const RANDOM_PROGRAMS: u64 = 500;
+const RANDOM_PROGRAM_INSTRUCTIONS: u16 = 48;
+const FRAMES: u64 = 30;
+
+fn assert_same_state(interpreter: &HeadlessRunner, compiled: &HeadlessRunner, program: &[u8]) {
+    assert_eq!(interpreter.cpu().inspect_register_file(), compiled.cpu().inspect_register_file(), "program {:02X?}", program);
+    assert_eq!(&interpreter.cpu().inspect_memory()[..], &compiled.cpu().inspect_memory()[..], "program {:02X?}", program);
+    assert_eq!(interpreter.screen().inspect_screen(), compiled.screen().inspect_screen(), "program {:02X?}", program);
+    assert_eq!(interpreter.frames(), compiled.frames(), "program {:02X?}", program);
+}
+
+// Steps the interpreter over every block the compiled backend runs and compares the whole machine after each one
+fn run_lockstep(program: &[u8], instructions_per_frame: u32) {
+    let mut interpreter = runner(program, instructions_per_frame, Quirks::default(), Backend::Interpreter);
+    let mut compiled = runner(program, instructions_per_frame, Quirks::default(), Backend::BlockCompiler);
+
+    while compiled.frames() < FRAMES {
+        match compiled.run_block() {
+            Ok(executed) => {
+                for _ in 0..executed {
+                    interpreter.step().unwrap();
+                }
+            },
+            // The instruction ending the block failed, so the interpreter has to fail on it as well
+            Err(_) => {
+                let failed = (0..=MAX_BLOCK_INSTRUCTIONS).any(|_| interpreter.step().is_err());
+                assert!(failed, "program {:02X?}", program);
+                assert_same_state(&interpreter, &compiled, program);
+                return;
+            },
+        };
+
+        assert_same_state(&interpreter, &compiled, program);
+    }
+}
+
+// Any instruction except ADD I, Vx, which could walk I out of RAM, with jumps and loads of I kept inside the program
+fn random_instruction(rng: &mut StdRng) -> u16 {
+    let x = rng.gen_range(0, 0x10) << 8;
+    let y = rng.gen_range(0, 0x10) << 4;
+    let byte = rng.gen::<u8>() as u16;
+    let address = 0x200 + 2 * rng.gen_range(0, RANDOM_PROGRAM_INSTRUCTIONS);
+
+    match rng.gen_range(0, 16) {
+        0 => 0x1000 | address,
+        1 => 0x2000 | address,
+        2 => 0x00EE,
+        3 => 0x3000 | x | byte,
+        4 => 0x4000 | x | byte,
+        5 => 0x5000 | x | y,
+        6 => 0x6000 | x | byte,
+        7 => 0x7000 | x | byte,
+        8 => 0x8000 | x | y | *[0x0, 0x1, 0x2, 0x3, 0x4, 0x5, 0x6, 0x7, 0xE].choose(rng).unwrap(),
+        9 => 0x9000 | x | y,
+        10 => 0xA000 | address,
+        11 => 0xB000 | address,
+        // The random bits are masked out so both backends see the same value
+        12 => 0xC000 | x,
+        13 => 0xD000 | x | y | rng.gen_range(1, 0x10),
+        14 => 0xE000 | x | *[0x9E, 0xA1].choose(rng).unwrap(),
+        _ => 0xF000 | x | *[0x07, 0x0A, 0x15, 0x18, 0x29, 0x33, 0x55, 0x65].choose(rng).unwrap(),
+    }
+}
+
+#[test]
+fn straight_line_arithmetic_matches_interpreter() {
+    let program = assemble(&[
+        0x6005, // LD V0, 05
+        0x61FF, // LD V1, FF
+        0x8014, // ADD V0, V1
+        0x8015, // SUB V0, V1
+        0x8017, // SUBN V0, V1
+        0x8016, // SHR V0
+        0x801E, // SHL V0
+        0x8011, // OR V0, V1
+        0x8012, // AND V0, V1
+        0x8013, // XOR V0, V1
+        0x8200, // LD V2, V0
+        0x7280, // ADD V2, 80
+        0xF215, // LD DT, V2
+        0xF318, // LD ST, V3
+        0xF407, // LD V4, DT
+        0xF029, // LD F, V0
+        0xF21E, // ADD I, V2
+        0xA200, // LD I, 200
+        0xF565, // LD V5, [I]
+        0x1200, // JP 200
+    ]);
+
+    for &instructions_per_frame in [1, 3, 8, 64].iter() {
+        run_lockstep(&program, instructions_per_frame);
+    }
+}
+
+#[test]
+fn self_modifying_writes_recompile_blocks() {
+    let program = assemble(&[
+        0xA20A, // 200: LD I, 20A
+        0x6075, // 202: LD V0, 75
+        0x6101, // 204: LD V1, 01
+        0x6500, // 206: LD V5, 00
+        0x7601, // 208: ADD V6, 01
+        0x6509, // 20A: LD V5, 09, overwritten with ADD V5, 01
+        0x6A02, // 20C: LD VA, 02
+        0x3602, // 20E: SE V6, 02
+        0x1216, // 210: JP 216
+        0x1214, // 212: JP 212
+        0x1214, // 214: JP 214
+        0xF155, // 216: LD [I], V1
+        0xA20C, // 218: LD I, 20C
+        0xF633, // 21A: LD B, V6
+        0x1208, // 21C: JP 208
+    ]);
+
+    for &instructions_per_frame in [1, 4, 100].iter() {
+        run_lockstep(&program, instructions_per_frame);
+    }
+}
+
+#[test]
+fn calls_and_draws_end_blocks() {
+    let program = assemble(&[
+        0x6000, // 200: LD V0, 00
+        0x6100, // 202: LD V1, 00
+        0x220C, // 204: CALL 20C
+        0x7008, // 206: ADD V0, 08
+        0x3040, // 208: SE V0, 40
+        0x1204, // 20A: JP 204
+        0xF029, // 20C: LD F, V0
+        0xD015, // 20E: DRW V0, V1, 5
+        0x7101, // 210: ADD V1, 01
+        0x00EE, // 212: RET
+    ]);
+
+    for &instructions_per_frame in [2, 7, 30].iter() {
+        run_lockstep(&program, instructions_per_frame);
+    }
+}
+
+#[test]
+fn random_programs_match_interpreter() {
+    for seed in 0..RANDOM_PROGRAMS {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let instructions: Vec<u16> = (0..RANDOM_PROGRAM_INSTRUCTIONS).map(|_| random_instruction(&mut rng)).collect();
+        let instructions_per_frame = rng.gen_range(1, 40);
+
+        run_lockstep(&assemble(&instructions), instructions_per_frame);
+    }
+}
@@ -0,0 +1,15 @@
+// Fixtures shared by the integration tests and benchmarks, each of which uses only some of them
+#![allow(dead_code)]
+
+use rip8::exec::headless::{ Backend, HeadlessRunner };
+use rip8::exec::quirks::Quirks;
+
+pub fn assemble(instructions: &[u16]) -> Vec<u8> {
+    instructions.iter().flat_map(|instruction| instruction.to_be_bytes().to_vec()).collect()
+}
+
+pub fn runner(program: &[u8], instructions_per_frame: u32, quirks: Quirks, backend: Backend) -> HeadlessRunner {
+    let mut runner = HeadlessRunner::new(program.to_vec(), instructions_per_frame, None, quirks).unwrap();
+    runner.set_backend(backend);
+    runner
+}
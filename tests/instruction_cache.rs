@@ -0,0 +1,93 @@
+mod common;
+
+use common::{ assemble, runner };
+use rip8::exec::headless::{ Backend, HeadlessRunner };
+use rip8::exec::quirks::Quirks;
+use rip8::mem::register::VRegister;
+
+const STEPS: usize = 500;
+
+fn cache_runner(program: &[u16], instruction_cache: bool) -> HeadlessRunner {
+    let mut runner = runner(&assemble(program), 8, Quirks::default(), Backend::Interpreter);
+    runner.set_instruction_cache(instruction_cache);
+    runner
+}
+
+// Steps a runner with the cache next to one decoding every instruction from RAM and compares them after each step
+fn run_with_and_without_cache(program: &[u16]) -> HeadlessRunner {
+    let mut cached = cache_runner(program, true);
+    let mut decoded = cache_runner(program, false);
+
+    for step in 0..STEPS {
+        let cached_result = cached.step();
+        let decoded_result = decoded.step();
+
+        assert_eq!(cached_result.is_ok(), decoded_result.is_ok(), "step {}", step);
+        assert_eq!(cached.cpu().inspect_register_file(), decoded.cpu().inspect_register_file(), "step {}", step);
+        assert_eq!(&cached.cpu().inspect_memory()[..], &decoded.cpu().inspect_memory()[..], "step {}", step);
+        if cached_result.is_err() {
+            break;
+        }
+    }
+
+    cached
+}
+
+#[test]
+fn stores_over_code_are_decoded() {
+    let runner = run_with_and_without_cache(&[
+        0x6A00, // 200: LD VA, 00
+        0x6B00, // 202: LD VB, 00
+        0x6C00, // 204: LD VC, 00
+        0x7C01, // 206: ADD VC, 01
+        0x6A05, // 208: LD VA, 05, overwritten with ADD VA, 05
+        0x7B03, // 20A: ADD VB, 03
+        0x3C06, // 20C: SE VC, 06
+        0x1214, // 20E: JP 214
+        0x1210, // 210: JP 210, only reached if the jump at 224 is never rewritten
+        0x0000, // 212
+        0xA208, // 214: LD I, 208
+        0x607A, // 216: LD V0, 7A
+        0x6105, // 218: LD V1, 05
+        0xF155, // 21A: LD [I], V1
+        0xA225, // 21C: LD I, 225
+        0x6DC8, // 21E: LD VD, C8
+        0xFD33, // 220: LD B, VD, 2 0 0 from 225
+        0x6E01, // 222: LD VE, 01
+        0x1206, // 224: JP 206, overwritten with JP 202
+        0x0000, // 226: written by LD B
+    ]);
+
+    let memory = runner.cpu().inspect_memory();
+    assert_eq!(memory[0x208..0x20A], [0x7A, 0x05]);
+    assert_eq!(memory[0x224..0x226], [0x12, 0x02]);
+    assert!(runner.cpu().inspect_register_file().get_v_register(VRegister::VC) < 6);
+}
+
+#[test]
+fn calls_over_code_are_decoded() {
+    // A CALL from 0E0 leaves 00E0 on the stack, over code placed at 000
+    let runner = run_with_and_without_cache(&[
+        0xA000, // 200: LD I, 000
+        0x606D, // 202: LD V0, 6D
+        0x6111, // 204: LD V1, 11
+        0x6212, // 206: LD V2, 12
+        0x630E, // 208: LD V3, 0E
+        0xF355, // 20A: LD [I], V3, 000: LD VD, 11 and 002: JP 20E
+        0x1000, // 20C: JP 000
+        0xA0E0, // 20E: LD I, 0E0
+        0x6022, // 210: LD V0, 22
+        0x6120, // 212: LD V1, 20
+        0x6212, // 214: LD V2, 12
+        0x631C, // 216: LD V3, 1C
+        0xF355, // 218: LD [I], V3, 0E0: CALL 220 and 0E2: JP 21C
+        0x10E0, // 21A: JP 0E0
+        0x1000, // 21C: JP 000, CLS once the CALL has run
+        0x0000, // 21E
+        0x6D00, // 220: LD VD, 00
+        0x00EE, // 222: RET
+    ]);
+
+    assert_eq!(runner.cpu().inspect_memory()[0x000..0x002], [0x00, 0xE0]);
+    assert_eq!(runner.cpu().inspect_register_file().get_v_register(VRegister::VD), 0x00);
+}
@@ -0,0 +1,185 @@
+mod common;
+
+use common::{ assemble, runner };
+use rip8::exec::error::EmulatorError;
+use rip8::exec::headless::{ Backend, HeadlessRunner };
+use rip8::exec::quirks::{ MemoryBounds, Quirks };
+use rip8::mem::register::VRegister;
+
+use std::error::Error;
+
+const BACKENDS: [Backend; 2] = [Backend::Interpreter, Backend::BlockCompiler];
+
+// Runs exactly the given number of instructions as a single frame
+fn run(program: &[u16], instructions: usize, memory_bounds: MemoryBounds,
+       backend: Backend) -> (HeadlessRunner, Result<(), Box<dyn Error>>) {
+    let quirks = Quirks { memory_bounds, ..Quirks::default() };
+    let mut runner = runner(&assemble(program), instructions as u32, quirks, backend);
+    let result = runner.run_frame();
+    (runner, result)
+}
+
+fn assert_out_of_bounds(result: Result<(), Box<dyn Error>>, pc: u16) {
+    match result.unwrap_err().downcast_ref::<EmulatorError>() {
+        Some(EmulatorError::RAMOutOfBounds(_, state)) => assert_eq!(state.pc, pc),
+        error => panic!("Expected an out of bounds error at {:03X}, got {:?}", pc, error),
+    }
+}
+
+fn v_registers(runner: &HeadlessRunner) -> [u8; 3] {
+    let registers = runner.cpu().inspect_register_file();
+    [
+        registers.get_v_register(VRegister::V0),
+        registers.get_v_register(VRegister::V1),
+        registers.get_v_register(VRegister::V2),
+    ]
+}
+
+// The program ends with a store of three bytes at I = FFE, the last of them one past the end of RAM
+fn assert_stores_past_end(program: &[u16], bytes: [u8; 3]) {
+    let store_address = 0x200 + 2 * (program.len() as u16 - 1);
+
+    for &backend in BACKENDS.iter() {
+        let (runner, result) = run(program, program.len(), MemoryBounds::Error, backend);
+        assert_out_of_bounds(result, store_address);
+        let memory = runner.cpu().inspect_memory();
+        assert_eq!([memory[0xFFE], memory[0xFFF]], [0, 0], "a failing store writes nothing");
+
+        let (runner, result) = run(program, program.len(), MemoryBounds::Wrap, backend);
+        result.unwrap();
+        let memory = runner.cpu().inspect_memory();
+        assert_eq!([memory[0xFFE], memory[0xFFF], memory[0x000]], bytes);
+
+        let (runner, result) = run(program, program.len(), MemoryBounds::Ignore, backend);
+        result.unwrap();
+        let memory = runner.cpu().inspect_memory();
+        assert_eq!([memory[0xFFE], memory[0xFFF], memory[0x000]], [bytes[0], bytes[1], 0]);
+    }
+}
+
+#[test]
+fn ld_array_past_end_of_ram() {
+    assert_stores_past_end(&[
+        0x6001, // 200: LD V0, 01
+        0x6102, // 202: LD V1, 02
+        0x6203, // 204: LD V2, 03
+        0xAFFE, // 206: LD I, FFE
+        0xF255, // 208: LD [I], V2
+    ], [1, 2, 3]);
+}
+
+#[test]
+fn ld_bcd_past_end_of_ram() {
+    assert_stores_past_end(&[
+        0x607B, // 200: LD V0, 7B
+        0xAFFE, // 202: LD I, FFE
+        0xF033, // 204: LD B, V0
+    ], [1, 2, 3]);
+}
+
+#[test]
+fn rd_array_past_end_of_ram() {
+    // I ends up at 10FE, which wraps to the byte before the 0 digit sprite
+    let program = [
+        0x6011, // 200: LD V0, 11
+        0x6111, // 202: LD V1, 11
+        0x6211, // 204: LD V2, 11
+        0xAFFF, // 206: LD I, FFF
+        0x63FF, // 208: LD V3, FF
+        0xF31E, // 20A: ADD I, V3
+        0xF265, // 20C: LD V2, [I]
+    ];
+
+    for &backend in BACKENDS.iter() {
+        let (runner, result) = run(&program, program.len(), MemoryBounds::Error, backend);
+        assert_out_of_bounds(result, 0x20C);
+        assert_eq!(v_registers(&runner), [0x11, 0x11, 0x11], "a failing load changes no registers");
+
+        let (runner, result) = run(&program, program.len(), MemoryBounds::Wrap, backend);
+        result.unwrap();
+        assert_eq!(v_registers(&runner), [0x00, 0xF0, 0x90]);
+
+        let (runner, result) = run(&program, program.len(), MemoryBounds::Ignore, backend);
+        result.unwrap();
+        assert_eq!(v_registers(&runner), [0x00, 0x00, 0x00]);
+    }
+}
+
+#[test]
+fn drw_past_end_of_ram() {
+    let program = [
+        0xAFFF, // 200: LD I, FFF
+        0x60FF, // 202: LD V0, FF
+        0xF01E, // 204: ADD I, V0
+        0x6100, // 206: LD V1, 00
+        0xD115, // 208: DRW V1, V1, 5
+    ];
+
+    for &backend in BACKENDS.iter() {
+        let (runner, result) = run(&program, program.len(), MemoryBounds::Error, backend);
+        assert_out_of_bounds(result, 0x208);
+        assert_eq!(runner.screen().inspect_last_drawn_sprite(), None);
+
+        let (runner, result) = run(&program, program.len(), MemoryBounds::Wrap, backend);
+        result.unwrap();
+        assert_eq!(runner.screen().inspect_last_drawn_sprite(), Some(vec![0x00, 0xF0, 0x90, 0x90, 0x90]));
+
+        let (runner, result) = run(&program, program.len(), MemoryBounds::Ignore, backend);
+        result.unwrap();
+        assert_eq!(runner.screen().inspect_last_drawn_sprite(), Some(vec![0x00; 5]));
+    }
+}
+
+#[test]
+fn fetch_past_end_of_ram() {
+    // The CALL leaves 02 at address 000, so the instruction at FFF wraps around to LD V0, 02
+    let program = [
+        0xAFFF, // 200: LD I, FFF
+        0x6060, // 202: LD V0, 60
+        0xF055, // 204: LD [I], V0
+        0x220A, // 206: CALL 20A
+        0x0000, // 208: unused
+        0x1FFF, // 20A: JP FFF
+    ];
+
+    for &backend in BACKENDS.iter() {
+        let (_, result) = run(&program, 6, MemoryBounds::Error, backend);
+        match result.unwrap_err().downcast_ref::<EmulatorError>() {
+            Some(EmulatorError::RAMOutOfBounds(_, state)) => {
+                assert_eq!(state.pc, 0xFFF);
+                assert_eq!(state.opcode, None);
+            },
+            error => panic!("Expected an out of bounds fetch, got {:?}", error),
+        }
+
+        let (runner, result) = run(&program, 6, MemoryBounds::Wrap, backend);
+        result.unwrap();
+        assert_eq!(v_registers(&runner)[0], 0x02);
+        assert_eq!(runner.cpu().inspect_register_file().PC, 0x001);
+
+        let (runner, result) = run(&program, 6, MemoryBounds::Ignore, backend);
+        result.unwrap();
+        assert_eq!(v_registers(&runner)[0], 0x00);
+        assert_eq!(runner.cpu().inspect_register_file().PC, 0x1001);
+    }
+}
+
+#[test]
+fn seventeenth_call_overflows_stack() {
+    // Each CALL goes to the next instruction, so the calls nest without returning
+    let program: Vec<u16> = (1..=17).map(|call| 0x2200 + 2 * call).collect();
+
+    for &memory_bounds in [MemoryBounds::Error, MemoryBounds::Wrap, MemoryBounds::Ignore].iter() {
+        let (runner, result) = run(&program, 16, memory_bounds, Backend::Interpreter);
+        result.unwrap();
+        assert_eq!(runner.cpu().inspect_stack().len(), 16);
+
+        let (runner, result) = run(&program, 17, memory_bounds, Backend::Interpreter);
+        match result.unwrap_err().downcast_ref::<EmulatorError>() {
+            Some(EmulatorError::InvalidStackPointer(_, state)) => assert_eq!(state.pc, 0x220),
+            error => panic!("Expected a stack overflow, got {:?}", error),
+        }
+        assert_eq!(runner.cpu().inspect_stack().len(), 16);
+        assert_eq!(runner.cpu().inspect_memory()[0x20..0x22], [0, 0], "the stack stays in its own region");
+    }
+}